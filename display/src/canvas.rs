@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+
+// A sparse grid of colours that grows in every direction. x runs right and y runs down, as on
+// a screen; anything never painted is colour 0.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Canvas {
+    cells: HashMap<(i64, i64), i64>
+}
+
+// The area covered by painted cells, inclusive at both ends.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: (i64, i64),
+    pub max: (i64, i64)
+}
+
+impl Bounds {
+    pub fn width(&self) -> usize {
+        (self.max.0 - self.min.0 + 1) as usize
+    }
+
+    pub fn height(&self) -> usize {
+        (self.max.1 - self.min.1 + 1) as usize
+    }
+}
+
+// RGB for colours 0 and 1 (black and white, like the hull), then a few more so other values
+// stay distinguishable. Colours past the end wrap around.
+const PALETTE: [[u8; 3]; 8] = [
+    [0, 0, 0], [255, 255, 255], [230, 60, 50], [60, 180, 75],
+    [0, 130, 200], [255, 225, 25], [145, 30, 180], [128, 128, 128]
+];
+
+pub fn rgb(colour: i64) -> [u8; 3] {
+    PALETTE[colour.rem_euclid(PALETTE.len() as i64) as usize]
+}
+
+impl Canvas {
+    pub fn new() -> Canvas {
+        Canvas::default()
+    }
+
+    pub fn get(&self, location: (i64, i64)) -> i64 {
+        self.cells.get(&location).copied().unwrap_or(0)
+    }
+
+    pub fn set(&mut self, location: (i64, i64), colour: i64) {
+        self.cells.insert(location, colour);
+    }
+
+    // How many cells have been painted at least once, whatever colour they ended up.
+    pub fn painted(&self) -> usize {
+        self.cells.len()
+    }
+
+    // The smallest box holding every cell that isn't 0, or None for a blank canvas.
+    pub fn bounds(&self) -> Option<Bounds> {
+        let mut lit = self.cells.iter().filter(|(_, colour)| **colour != 0).map(|(location, _)| *location);
+        let first = lit.next()?;
+        Some(lit.fold(Bounds { min: first, max: first }, |b, (x, y)| Bounds {
+            min: (b.min.0.min(x), b.min.1.min(y)),
+            max: (b.max.0.max(x), b.max.1.max(y))
+        }))
+    }
+
+    // The colours inside the bounds, a row at a time from the top.
+    pub fn crop(&self) -> Vec<Vec<i64>> {
+        self.bounds().map_or_else(Vec::new, |b| self.region(b))
+    }
+
+    // The colours inside some other bounds, so frames of an animation can share a size.
+    pub fn region(&self, b: Bounds) -> Vec<Vec<i64>> {
+        (b.min.1..=b.max.1)
+            .map(|y| (b.min.0..=b.max.0).map(|x| self.get((x, y))).collect())
+            .collect()
+    }
+
+    // Netpbm P4: one bit a pixel, set for anything that isn't 0, each cell scale pixels across.
+    pub fn to_pbm(&self, scale: usize) -> Vec<u8> {
+        let rows = scaled(&self.crop(), scale);
+        let width = rows.first().map_or(0, Vec::len);
+        let mut pbm = format!("P4\n{} {}\n", width, rows.len()).into_bytes();
+        for row in &rows {
+            for byte in row.chunks(8) {
+                pbm.push(byte.iter().enumerate().fold(0, |bits, (i, colour)| bits | ((*colour != 0) as u8) << (7 - i)));
+            }
+        }
+        pbm
+    }
+
+    pub fn to_ppm(&self, scale: usize) -> Vec<u8> {
+        ppm(&self.crop(), scale)
+    }
+
+    // An indexed-colour PNG using PALETTE.
+    pub fn to_png(&self, scale: usize) -> Vec<u8> {
+        let rows = scaled(&self.crop(), scale);
+        let width = rows.first().map_or(0, Vec::len);
+        let mut raw = vec!();
+        for row in &rows {
+            raw.push(0);
+            raw.extend(row.iter().map(|colour| index(*colour)));
+        }
+
+        let mut header = vec!();
+        header.extend((width as u32).to_be_bytes());
+        header.extend((rows.len() as u32).to_be_bytes());
+        header.extend([8, 3, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        chunk(&mut png, b"IHDR", &header);
+        chunk(&mut png, b"PLTE", &PALETTE.concat());
+        chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn scaled(rows: &[Vec<i64>], scale: usize) -> Vec<Vec<i64>> {
+    rows.iter()
+        .flat_map(|row| {
+            let row: Vec<i64> = row.iter().flat_map(|colour| std::iter::repeat_n(*colour, scale)).collect();
+            std::iter::repeat_n(row, scale)
+        })
+        .collect()
+}
+
+fn index(colour: i64) -> u8 {
+    colour.rem_euclid(PALETTE.len() as i64) as u8
+}
+
+// Netpbm P6: full colour from PALETTE.
+pub fn ppm(rows: &[Vec<i64>], scale: usize) -> Vec<u8> {
+    let rows = scaled(rows, scale);
+    let width = rows.first().map_or(0, Vec::len);
+    let mut ppm = format!("P6\n{} {}\n255\n", width, rows.len()).into_bytes();
+    for row in &rows {
+        ppm.extend(row.iter().flat_map(|colour| rgb(*colour)));
+    }
+    ppm
+}
+
+// An animated GIF that loops forever, showing each frame for delay hundredths of a second.
+// Every frame should be the same size as the first, which once scaled can be at most 65535
+// pixels each way.
+pub fn gif(frames: &[Vec<Vec<i64>>], scale: usize, delay: u16) -> io::Result<Vec<u8>> {
+    let first = frames.first().map(Vec::as_slice).unwrap_or_default();
+    let size = |pixels: usize| u16::try_from(pixels.saturating_mul(scale)).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("a gif can't be more than {} pixels across or down", u16::MAX))
+    });
+    let (width, height) = (size(first.first().map_or(0, Vec::len))?, size(first.len())?);
+
+    let mut gif = b"GIF89a".to_vec();
+    gif.extend(width.to_le_bytes());
+    gif.extend(height.to_le_bytes());
+    // a global table of 8 colours
+    gif.extend([0xa2, 0, 0]);
+    gif.extend(PALETTE.concat());
+    gif.extend(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+
+    for rows in frames {
+        gif.extend([0x21, 0xf9, 4, 0]);
+        gif.extend(delay.to_le_bytes());
+        gif.extend([0, 0]);
+
+        gif.extend([0x2c, 0, 0, 0, 0]);
+        gif.extend(width.to_le_bytes());
+        gif.extend(height.to_le_bytes());
+        gif.push(0);
+
+        let pixels: Vec<u8> = scaled(rows, scale).iter().flatten().map(|colour| index(*colour)).collect();
+        gif.push(GIF_MIN_CODE_SIZE);
+        for block in lzw(&pixels).chunks(u8::MAX as usize) {
+            gif.push(u8::try_from(block.len()).expect("sub-blocks fit in a byte"));
+            gif.extend(block);
+        }
+        gif.push(0);
+    }
+    gif.push(0x3b);
+    Ok(gif)
+}
+
+const GIF_MIN_CODE_SIZE: u8 = 3;
+
+// GIF's variable-width LZW, starting again with a clear code whenever the table fills up.
+fn lzw(pixels: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << GIF_MIN_CODE_SIZE;
+    let end = clear + 1;
+    let mut out = vec!();
+    let (mut bits, mut pending) = (0u32, 0u32);
+    let mut emit = |code: u16, size: u32| {
+        bits |= (code as u32) << pending;
+        pending += size;
+        while pending >= 8 {
+            out.push(bits as u8);
+            bits >>= 8;
+            pending -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = GIF_MIN_CODE_SIZE as u32 + 1;
+    let mut next = end + 1;
+    emit(clear, size);
+    let mut pixels = pixels.iter();
+    if let Some(first) = pixels.next() {
+        let mut code = *first as u16;
+        for pixel in pixels {
+            if let Some(longer) = table.get(&(code, *pixel)) {
+                code = *longer;
+                continue;
+            }
+            emit(code, size);
+            if next == 4096 {
+                emit(clear, size);
+                table.clear();
+                size = GIF_MIN_CODE_SIZE as u32 + 1;
+                next = end + 1;
+            } else {
+                table.insert((code, *pixel), next);
+                next += 1;
+                // the decoder grows its codes one entry behind
+                if next > 1 << size {
+                    size += 1;
+                }
+            }
+            code = *pixel as u16;
+        }
+        emit(code, size);
+        // the decoder adds an entry for that last code too, and may widen before the end code
+        if next < 4096 && next + 1 > 1 << size {
+            size += 1;
+        }
+    }
+    emit(end, size);
+    if pending > 0 {
+        out.push(bits as u8);
+    }
+    out
+}
+
+// The cropped canvas in block characters, 1 as a full block and other colours shaded.
+impl fmt::Display for Canvas {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.crop() {
+            let line: String = row.iter().map(|colour| match colour {
+                0 => ' ',
+                1 => '█',
+                _ => '▒'
+            }).collect();
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 })
+    })
+}
+
+// A zlib stream of uncompressed deflate blocks. The images are small enough that compressing
+// them isn't worth a dependency.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec!(0x78, 0x01);
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        out.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = u16::try_from(block.len()).expect("stored blocks fit in 16 bits");
+        out.push(blocks.peek().is_none() as u8);
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), byte| {
+        let a = (a + *byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    out.extend((b << 16 | a).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn letter_l() -> Canvas {
+        let mut canvas = Canvas::new();
+        for y in -3..=-1 {
+            canvas.set((-5, y), 1);
+        }
+        canvas.set((-4, -1), 1);
+        canvas.set((-4, -2), 0);
+        canvas
+    }
+
+    #[test]
+    fn test_crop_and_render() {
+        let canvas = letter_l();
+        assert_eq!(canvas.painted(), 5);
+        assert_eq!(canvas.bounds(), Some(Bounds { min: (-5, -3), max: (-4, -1) }));
+        assert_eq!(canvas.crop(), vec!(vec!(1, 0), vec!(1, 0), vec!(1, 1)));
+        assert_eq!(canvas.to_string(), "█\n█\n██\n");
+        assert_eq!(Canvas::new().to_string(), "");
+
+        let mut canvas = Canvas::new();
+        canvas.set((1_000_000, 0), 7);
+        canvas.set((-1_000_000, 0), 1);
+        assert_eq!(canvas.bounds().unwrap().width(), 2_000_001);
+    }
+
+    #[test]
+    fn test_pbm() {
+        let pbm = letter_l().to_pbm(1);
+        assert_eq!(pbm, b"P4\n2 3\n\x80\x80\xc0");
+        let pbm = letter_l().to_pbm(5);
+        assert!(pbm.starts_with(b"P4\n10 15\n"));
+        assert_eq!(pbm.len(), 9 + 15 * 2);
+        assert_eq!(&pbm[9..11], &[0xf8, 0x00]);
+    }
+
+    #[test]
+    fn test_png() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(zlib_stored(b""), vec!(0x78, 0x01, 1, 0, 0, 0xff, 0xff, 0, 0, 0, 1));
+
+        let png = letter_l().to_png(1);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 3]);
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+        let idat = png.windows(4).position(|w| w == b"IDAT").unwrap();
+        // filter byte then one palette index a pixel, stored as-is
+        assert_eq!(&png[idat + 11..idat + 20], &[0, 1, 0, 0, 1, 0, 0, 1, 1]);
+    }
+
+    // a plain GIF LZW decoder to check the encoder against
+    fn unlzw(data: &[u8]) -> Vec<u8> {
+        let clear = 1usize << GIF_MIN_CODE_SIZE;
+        let mut table: Vec<Vec<u8>> = vec!();
+        let mut size = 0;
+        let (mut bits, mut pending, mut bytes) = (0u32, 0, data.iter());
+        let mut previous: Option<Vec<u8>> = None;
+        let mut out = vec!();
+        loop {
+            while pending < size.max(GIF_MIN_CODE_SIZE as u32 + 1) {
+                bits |= (*bytes.next().expect("ran out of data") as u32) << pending;
+                pending += 8;
+            }
+            let width = size.max(GIF_MIN_CODE_SIZE as u32 + 1);
+            let code = (bits & ((1 << width) - 1)) as usize;
+            bits >>= width;
+            pending -= width;
+
+            if code == clear {
+                table = (0..clear as u8).map(|i| vec!(i)).collect();
+                table.extend([vec!(), vec!()]);
+                size = GIF_MIN_CODE_SIZE as u32 + 1;
+                previous = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => [previous.clone(), vec!(previous[0])].concat(),
+                (None, None) => panic!("bad first code {}", code)
+            };
+            out.extend(&entry);
+            if let Some(previous) = previous {
+                if table.len() < 4096 {
+                    table.push([previous, vec!(entry[0])].concat());
+                    if table.len() == 1 << size && size < 12 {
+                        size += 1;
+                    }
+                }
+            }
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_ppm() {
+        let ppm = letter_l().to_ppm(1);
+        assert!(ppm.starts_with(b"P6\n2 3\n255\n"));
+        assert_eq!(ppm.len(), 11 + 2 * 3 * 3);
+        assert_eq!(&ppm[11..17], &[255, 255, 255, 0, 0, 0]);
+        assert_eq!(rgb(-1), rgb(7));
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..20000).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8 % 8
+        }).collect();
+        let runs: Vec<u8> = (0..50000).map(|i| (i / 37 % 5) as u8).collect();
+        for pixels in [vec!(), vec!(3), vec!(0; 5000), noise, runs] {
+            assert_eq!(unlzw(&lzw(&pixels)), pixels);
+        }
+        for len in 0..600 {
+            let pixels: Vec<u8> = (0..len).map(|i| (i * i % 7) as u8).collect();
+            assert_eq!(unlzw(&lzw(&pixels)), pixels, "{} pixels", len);
+        }
+    }
+
+    #[test]
+    fn test_gif() {
+        let frames = vec!(letter_l().crop(), vec!(vec!(2, 2), vec!(0, 0), vec!(4, 4)));
+        let gif = gif(&frames, 2, 10).unwrap();
+        assert!(gif.starts_with(b"GIF89a\x04\x00\x06\x00\xa2"));
+        assert_eq!(gif.windows(11).filter(|w| *w == b"NETSCAPE2.0").count(), 1);
+        assert_eq!(gif.windows(4).filter(|w| *w == [0x21, 0xf9, 4, 0]).count(), 2);
+        assert_eq!(gif.last(), Some(&0x3b));
+
+        // the first frame's pixels, after its image descriptor and code size
+        let start = 13 + 24 + 19 + 8 + 10 + 1;
+        let data = &gif[start + 1..start + 1 + gif[start] as usize];
+        assert_eq!(unlzw(data), vec!(1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1));
+    }
+
+    #[test]
+    fn test_gif_size_limit() {
+        assert!(gif(&[vec!(vec!(0; 65535))], 1, 0).is_ok());
+        let error = gif(&[vec!(vec!(0; 40000))], 2, 0).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(gif(&[vec!(vec!(0); 70000)], 1, 0).is_err());
+        assert!(gif(&[vec!(vec!(0))], usize::MAX, 0).is_err());
+    }
+}
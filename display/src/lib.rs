@@ -0,0 +1,4 @@
+// Pictures that don't need an Intcode machine to make or read: painted grids, image export, and
+// the puzzles' block-letter font.
+pub mod canvas;
+pub mod ocr;
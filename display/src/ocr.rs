@@ -0,0 +1,149 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::canvas::Canvas;
+
+const HEIGHT: usize = 6;
+
+// The puzzles' block-letter font, 6 rows high and usually 4 columns wide with a blank column
+// between letters. Y is 5 wide and runs straight into whatever follows it.
+const GLYPHS: [(char, [&str; HEIGHT]); 18] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', ["###", ".#.", ".#.", ".#.", ".#.", "###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Y', ["#...#", "#...#", ".#.#.", "..#..", "..#..", "..#.."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"])
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OcrError {
+    // Text in this font is exactly 6 rows high once blank rows are trimmed off.
+    Height(usize),
+    // Nothing in the font matches the pixels starting at this column.
+    Unknown { column: usize }
+}
+
+impl fmt::Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OcrError::Height(height) => write!(f, "text is {} rows high, not {}", height, HEIGHT),
+            OcrError::Unknown { column } => write!(f, "unrecognised letter at column {}", column)
+        }
+    }
+}
+
+impl Error for OcrError {}
+
+// Reads letters off rows of pixels. Blank rows and columns around and between the letters
+// don't matter.
+pub fn read(rows: &[Vec<bool>]) -> Result<String, OcrError> {
+    let lit_rows: Vec<usize> = (0..rows.len()).filter(|y| rows[*y].contains(&true)).collect();
+    let rows = match (lit_rows.first(), lit_rows.last()) {
+        (Some(top), Some(bottom)) => &rows[*top..=*bottom],
+        _ => return Ok(String::new())
+    };
+    if rows.len() != HEIGHT {
+        return Err(OcrError::Height(rows.len()));
+    }
+
+    let pixel = |x: usize, y: usize| rows[y].get(x).copied().unwrap_or(false);
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let mut text = String::new();
+    let mut x = 0;
+    while x < width {
+        if (0..HEIGHT).all(|y| !pixel(x, y)) {
+            x += 1;
+            continue;
+        }
+        // the widest match wins, so a narrow letter can't match the front of a wider one
+        let (letter, glyph_width) = GLYPHS.iter()
+            .filter(|(_, glyph)| glyph.iter().enumerate().all(|(y, row)| {
+                row.chars().enumerate().all(|(dx, c)| pixel(x + dx, y) == (c == '#'))
+            }))
+            .map(|(letter, glyph)| (*letter, glyph[0].len()))
+            .max_by_key(|(_, glyph_width)| *glyph_width)
+            .ok_or(OcrError::Unknown { column: x })?;
+        text.push(letter);
+        x += glyph_width;
+    }
+    Ok(text)
+}
+
+// Reads a picture drawn in text, where spaces and dots are blank and anything else is lit.
+pub fn read_str(picture: &str) -> Result<String, OcrError> {
+    let rows: Vec<Vec<bool>> = picture.lines()
+        .map(|line| line.chars().map(|c| c != ' ' && c != '.').collect())
+        .collect();
+    read(&rows)
+}
+
+// Reads the cropped canvas, where any colour but 0 is lit.
+pub fn read_canvas(canvas: &Canvas) -> Result<String, OcrError> {
+    let rows: Vec<Vec<bool>> = canvas.crop().iter()
+        .map(|row| row.iter().map(|colour| *colour != 0).collect())
+        .collect();
+    read(&rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_whole_font() {
+        let mut picture = vec!(String::new(); HEIGHT);
+        for (_, glyph) in GLYPHS.iter() {
+            for (line, row) in picture.iter_mut().zip(glyph.iter()) {
+                line.push_str(row);
+                line.push('.');
+            }
+        }
+        assert_eq!(read_str(&picture.join("\n")), Ok("ABCEFGHIJKLOPRSUYZ".to_string()));
+    }
+
+    #[test]
+    fn test_y_runs_into_next_letter() {
+        let picture = "
+            @   @ @@  @@@  @   @@@@@
+            @   @@  @ @  @ @   @   @
+             @ @ @    @  @  @ @   @
+              @  @ @@ @@@    @   @
+              @  @  @ @ @    @  @
+              @   @@@ @  @   @  @@@@
+        ";
+        let picture: String = picture.lines().map(|line| line.get(12..).unwrap_or("")).collect::<Vec<_>>().join("\n");
+        assert_eq!(read_str(&picture), Ok("YGRYZ".to_string()));
+    }
+
+    #[test]
+    fn test_canvas() {
+        let mut canvas = Canvas::new();
+        assert_eq!(read_canvas(&canvas), Ok(String::new()));
+        for (y, row) in GLYPHS[10].1.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                canvas.set((x as i64 - 40, y as i64 + 9), (c == '#') as i64 * 5);
+            }
+        }
+        assert_eq!(read_canvas(&canvas), Ok("L".to_string()));
+
+        canvas.set((-30, 8), 1);
+        assert_eq!(read_canvas(&canvas), Err(OcrError::Height(7)));
+        canvas.set((-30, 8), 0);
+        canvas.set((-30, 9), 1);
+        assert_eq!(read_canvas(&canvas), Err(OcrError::Unknown { column: 10 }));
+        assert_eq!(OcrError::Unknown { column: 10 }.to_string(), "unrecognised letter at column 10");
+    }
+}
@@ -0,0 +1,504 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use display::canvas::{self, Bounds, Canvas};
+use crate::snapshot::Snapshot;
+use crate::{IoDevice, Vm};
+
+pub const EMPTY: i64 = 0;
+pub const WALL: i64 = 1;
+pub const BLOCK: i64 = 2;
+pub const PADDLE: i64 = 3;
+pub const BALL: i64 = 4;
+
+const HEADER: &str = "intcode-arcade 1";
+
+// What the cabinet is showing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Screen {
+    pub tiles: Canvas,
+    pub score: i64,
+    pub paddle: Option<(i64, i64)>,
+    pub ball: Option<(i64, i64)>
+}
+
+impl Screen {
+    pub fn draw(&mut self, x: i64, y: i64, tile: i64) {
+        self.tiles.set((x, y), tile);
+        match tile {
+            PADDLE => self.paddle = Some((x, y)),
+            BALL => self.ball = Some((x, y)),
+            _ => {}
+        }
+    }
+
+    pub fn apply(&mut self, frame: &Frame) {
+        for (x, y, tile) in &frame.tiles {
+            self.tiles.set((*x, *y), *tile);
+        }
+        self.score = frame.score;
+        self.paddle = frame.paddle;
+        self.ball = frame.ball;
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.tiles.crop().iter().flatten().filter(|tile| **tile == BLOCK).count()
+    }
+
+    // The output that would draw this screen from blank, score last.
+    pub fn redraw(&self) -> Vec<i64> {
+        let mut outputs = vec!();
+        if let Some(bounds) = self.tiles.bounds() {
+            for (row, y) in self.tiles.region(bounds).iter().zip(bounds.min.1..) {
+                for (tile, x) in row.iter().zip(bounds.min.0..) {
+                    if *tile != EMPTY {
+                        outputs.extend([x, y, *tile]);
+                    }
+                }
+            }
+        }
+        outputs.extend([-1, 0, self.score]);
+        outputs
+    }
+}
+
+impl fmt::Display for Screen {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "score {}", self.score)?;
+        for row in self.tiles.crop() {
+            let line: String = row.iter().map(|tile| match *tile {
+                EMPTY => ' ',
+                WALL => '█',
+                BLOCK => '▒',
+                PADDLE => '▔',
+                BALL => 'o',
+                _ => '?'
+            }).collect();
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+// Everything that changed between two reads of the joystick, and where things ended up.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Frame {
+    pub tiles: Vec<(i64, i64, i64)>,
+    pub score: i64,
+    pub paddle: Option<(i64, i64)>,
+    pub ball: Option<(i64, i64)>,
+    // The tilt the game read at the end of the frame. The last frame of a game has none, and
+    // neither does one cut short by loading a snapshot.
+    pub joystick: Option<i64>
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    pub frames: Vec<Frame>
+}
+
+pub trait Joystick {
+    // Which way to tilt, -1, 0 or 1, or None to leave the game waiting.
+    fn tilt(&mut self, screen: &Screen) -> Option<i64>;
+}
+
+// Keeps the paddle under the ball.
+pub struct Autopilot;
+
+impl Joystick for Autopilot {
+    fn tilt(&mut self, screen: &Screen) -> Option<i64> {
+        match (screen.paddle, screen.ball) {
+            (Some(paddle), Some(ball)) => Some((ball.0 - paddle.0).signum()),
+            _ => Some(0)
+        }
+    }
+}
+
+// The cabinet around the game: it draws the output on a Screen, asks the joystick for input and
+// records a frame each time it does.
+pub struct Arcade<J> {
+    pub screen: Screen,
+    pub joystick: J,
+    pub recording: Recording,
+    pending: Vec<i64>,
+    changes: Vec<(i64, i64, i64)>
+}
+
+impl<J: Joystick> Arcade<J> {
+    pub fn new(joystick: J) -> Arcade<J> {
+        Arcade { screen: Screen::default(), joystick, recording: Recording::default(), pending: vec!(), changes: vec!() }
+    }
+
+    fn end_frame(&mut self, joystick: Option<i64>) {
+        self.recording.frames.push(Frame {
+            tiles: std::mem::take(&mut self.changes),
+            score: self.screen.score,
+            paddle: self.screen.paddle,
+            ball: self.screen.ball,
+            joystick
+        });
+    }
+
+    // A snapshot of the game for picking up later. The game only draws what changes, so the
+    // snapshot's outputs hold a redraw of the screen as well.
+    pub fn save(&self, vm: &Vm) -> Snapshot {
+        let mut snapshot = vm.snapshot();
+        snapshot.outputs = self.screen.redraw();
+        snapshot
+    }
+
+    // Puts the machine and the screen back how they were saved. The redraw goes into the
+    // recording like anything else the game draws.
+    pub fn load(&mut self, vm: &mut Vm, snapshot: &Snapshot) {
+        if !self.changes.is_empty() {
+            self.end_frame(None);
+        }
+        vm.restore(snapshot);
+        vm.outputs.clear();
+        self.screen = Screen::default();
+        self.pending.clear();
+        for value in &snapshot.outputs {
+            self.output(*value);
+        }
+    }
+
+    // Records whatever was drawn since the joystick was last read, once the game is over.
+    pub fn finish(&mut self) -> &Recording {
+        let last = self.recording.frames.last();
+        if !self.changes.is_empty() || last.is_none_or(|frame| frame.score != self.screen.score) {
+            self.end_frame(None);
+        }
+        &self.recording
+    }
+}
+
+impl<J: Joystick> IoDevice for Arcade<J> {
+    fn input(&mut self) -> Option<i64> {
+        let tilt = self.joystick.tilt(&self.screen)?;
+        self.end_frame(Some(tilt));
+        Some(tilt)
+    }
+
+    fn output(&mut self, value: i64) {
+        self.pending.push(value);
+        if let [x, y, value] = self.pending[..] {
+            self.pending.clear();
+            if (x, y) == (-1, 0) {
+                self.screen.score = value;
+            } else {
+                self.screen.draw(x, y, value);
+                self.changes.push((x, y, value));
+            }
+        }
+    }
+}
+
+// Steps through a recording, forwards or backwards.
+pub struct Player<'a> {
+    pub recording: &'a Recording,
+    pub screen: Screen,
+    // How many frames have been applied to the screen.
+    pub position: usize
+}
+
+impl<'a> Player<'a> {
+    pub fn new(recording: &'a Recording) -> Player<'a> {
+        Player { recording, screen: Screen::default(), position: 0 }
+    }
+
+    // Shows the next frame, if there is one.
+    pub fn step(&mut self) -> bool {
+        match self.recording.frames.get(self.position) {
+            Some(frame) => {
+                self.screen.apply(frame);
+                self.position += 1;
+                true
+            },
+            None => false
+        }
+    }
+
+    // Shows the screen as it was at the end of the given frame. Going back replays from the start.
+    pub fn seek(&mut self, frame: usize) {
+        if frame < self.position {
+            self.screen = Screen::default();
+            self.position = 0;
+        }
+        while self.position <= frame && self.step() {}
+    }
+}
+
+impl Recording {
+    // The area every frame fits in.
+    pub fn bounds(&self) -> Option<Bounds> {
+        let mut player = Player::new(self);
+        player.seek(self.frames.len());
+        player.screen.tiles.bounds()
+    }
+
+    // The tiles of every frame, from the start, all cut to the same bounds.
+    pub fn pictures(&self) -> Vec<Vec<Vec<i64>>> {
+        let bounds = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return vec!()
+        };
+        let mut player = Player::new(self);
+        let mut pictures = vec!();
+        while player.step() {
+            pictures.push(player.screen.tiles.region(bounds));
+        }
+        pictures
+    }
+
+    pub fn to_gif(&self, scale: usize, delay: u16) -> io::Result<Vec<u8>> {
+        canvas::gif(&self.pictures(), scale, delay)
+    }
+
+    pub fn to_ppms(&self, scale: usize) -> Vec<Vec<u8>> {
+        self.pictures().iter().map(|picture| canvas::ppm(picture, scale)).collect()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn load(path: &Path) -> io::Result<Recording> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+fn position(location: Option<(i64, i64)>) -> String {
+    location.map_or("-".to_string(), |(x, y)| format!("{},{}", x, y))
+}
+
+fn parse_numbers(text: &str) -> Result<Vec<i64>, String> {
+    text.split(',').map(|tok| tok.parse::<i64>().map_err(|_| format!("bad number {}", tok))).collect()
+}
+
+fn parse_position(text: &str) -> Result<Option<(i64, i64)>, String> {
+    if text == "-" {
+        return Ok(None);
+    }
+    match parse_numbers(text)?[..] {
+        [x, y] => Ok(Some((x, y))),
+        _ => Err(format!("bad position {}", text))
+    }
+}
+
+// One frame a line: joystick, score, paddle, ball and then the tiles drawn as x,y,tile.
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for frame in &self.frames {
+            let joystick = frame.joystick.map_or("-".to_string(), |tilt| tilt.to_string());
+            write!(f, "{} {} {} {}", joystick, frame.score, position(frame.paddle), position(frame.ball))?;
+            for (x, y, tile) in &frame.tiles {
+                write!(f, " {},{},{}", x, y, tile)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Recording {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Recording, String> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("not an arcade recording".to_string());
+        }
+
+        let mut frames = vec!();
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 {
+                return Err(format!("short frame {}", line));
+            }
+            let joystick = match fields[0] {
+                "-" => None,
+                tilt => Some(tilt.parse::<i64>().map_err(|_| format!("bad joystick {}", tilt))?)
+            };
+            let score = fields[1].parse::<i64>().map_err(|_| format!("bad score {}", fields[1]))?;
+            let tiles = fields[4..].iter()
+                .map(|tile| match parse_numbers(tile)?[..] {
+                    [x, y, tile] => Ok((x, y, tile)),
+                    _ => Err(format!("bad tile {}", tile))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            frames.push(Frame { tiles, score, paddle: parse_position(fields[2])?, ball: parse_position(fields[3])?, joystick });
+        }
+        Ok(Recording { frames })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::Vm;
+
+    // A tiny game: walls either side, a block, and a ball that moves right twice while the
+    // paddle follows it, scoring a point each time.
+    fn game() -> Vec<i64> {
+        assemble("
+                OUT #0
+                OUT #0
+                OUT #1
+                OUT #3
+                OUT #0
+                OUT #1
+                OUT #2
+                OUT #0
+                OUT #2
+                OUT #1
+                OUT #2
+                OUT #3
+                OUT #1
+                OUT #1
+                OUT #4
+            loop:
+                IN [tilt]
+                OUT [paddle]
+                OUT #2
+                OUT #0
+                ADD [paddle], [tilt], [paddle]
+                OUT [paddle]
+                OUT #2
+                OUT #3
+                OUT [ball]
+                OUT #1
+                OUT #0
+                ADD [ball], #1, [ball]
+                OUT [ball]
+                OUT #1
+                OUT #4
+                OUT #-1
+                OUT #0
+                ADD [score], #1, [score]
+                OUT [score]
+                ADD [turns], #-1, [turns]
+                JNZ [turns], #loop
+                OUT #2
+                OUT #0
+                OUT #0
+                HLT
+            tilt: db 0
+            paddle: db 1
+            ball: db 1
+            score: db 0
+            turns: db 2
+        ").unwrap()
+    }
+
+    fn play() -> Arcade<Autopilot> {
+        let mut arcade = Arcade::new(Autopilot);
+        Vm::new(game()).run_with(&mut arcade).unwrap();
+        arcade.finish();
+        arcade
+    }
+
+    #[test]
+    fn test_records_frames() {
+        let arcade = play();
+        let frames = &arcade.recording.frames;
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].tiles.len(), 5);
+        assert_eq!(frames[0].joystick, Some(0));
+        assert_eq!(frames[1], Frame { tiles: vec!((1, 2, 0), (1, 2, 3), (1, 1, 0), (2, 1, 4)), score: 1, paddle: Some((1, 2)), ball: Some((2, 1)), joystick: Some(1) });
+        assert_eq!(frames[2].tiles, vec!((1, 2, 0), (2, 2, 3), (2, 1, 0), (3, 1, 4), (2, 0, 0)));
+        assert_eq!(frames[2].joystick, None);
+        assert_eq!(arcade.screen.score, 2);
+        assert_eq!(arcade.screen.blocks(), 0);
+        assert_eq!(arcade.screen.to_string(), "score 2\n█  █\n   o\n  ▔ \n");
+    }
+
+    // Plays on autopilot but stops after a number of tilts.
+    struct Limited(usize);
+
+    impl Joystick for Limited {
+        fn tilt(&mut self, screen: &Screen) -> Option<i64> {
+            self.0 = self.0.checked_sub(1)?;
+            Autopilot.tilt(screen)
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut vm = Vm::new(game());
+        let mut arcade = Arcade::new(Limited(1));
+        vm.run_with(&mut arcade).unwrap();
+        assert!(vm.needs_input());
+        let saved = arcade.save(&vm);
+        let screen = arcade.screen.to_string();
+        assert_eq!(saved.outputs, vec!(0, 0, 1, 2, 0, 2, 3, 0, 1, 2, 1, 4, 1, 2, 3, -1, 0, 1));
+
+        arcade.joystick = Limited(5);
+        vm.run_with(&mut arcade).unwrap();
+        assert!(vm.halted());
+        let finished = arcade.screen.to_string();
+
+        // cells drawn empty before the save aren't brought back, but nothing visible differs
+        arcade.load(&mut vm, &saved);
+        assert_eq!(arcade.screen.to_string(), screen);
+        assert!(vm.outputs.is_empty());
+        vm.run_with(&mut arcade).unwrap();
+        assert!(vm.halted());
+        assert_eq!(arcade.screen.to_string(), finished);
+        let frames = &arcade.recording.frames;
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[2].joystick, None);
+        assert_eq!(frames[3].tiles.len(), 5);
+    }
+
+    #[test]
+    fn test_player_seeks() {
+        let arcade = play();
+        let mut player = Player::new(&arcade.recording);
+        player.seek(0);
+        assert_eq!(player.screen.blocks(), 1);
+        assert_eq!(player.screen.ball, Some((1, 1)));
+        player.seek(2);
+        assert_eq!(player.screen, arcade.screen);
+        player.seek(1);
+        assert_eq!((player.position, player.screen.score, player.screen.blocks()), (2, 1, 1));
+        assert!(player.step());
+        assert!(!player.step());
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let recording = play().recording;
+        let text = recording.to_string();
+        assert!(text.starts_with("intcode-arcade 1\n0 0 1,2 1,1 0,0,1 3,0,1 2,0,2 1,2,3 1,1,4\n"));
+        assert!(text.ends_with("\n- 2 2,2 3,1 1,2,0 2,2,3 2,1,0 3,1,4 2,0,0\n"));
+        assert_eq!(text.parse::<Recording>(), Ok(recording));
+
+        assert_eq!("intcode-arcade 1\n0 0 - -\n".parse::<Recording>().unwrap().frames[0], Frame { joystick: Some(0), ..Frame::default() });
+        assert_eq!("nope".parse::<Recording>().unwrap_err(), "not an arcade recording");
+        assert_eq!("intcode-arcade 1\n0 0 1,2\n".parse::<Recording>().unwrap_err(), "short frame 0 0 1,2");
+        assert_eq!("intcode-arcade 1\n0 0 - - 1,2\n".parse::<Recording>().unwrap_err(), "bad tile 1,2");
+        assert_eq!("intcode-arcade 1\nx 0 - -\n".parse::<Recording>().unwrap_err(), "bad joystick x");
+    }
+
+    #[test]
+    fn test_exports() {
+        let recording = play().recording;
+        assert_eq!(recording.bounds(), Some(Bounds { min: (0, 0), max: (3, 2) }));
+        let pictures = recording.pictures();
+        assert_eq!(pictures.len(), 3);
+        assert_eq!(pictures[0], vec!(vec!(1, 0, 2, 1), vec!(0, 4, 0, 0), vec!(0, 3, 0, 0)));
+
+        let ppms = recording.to_ppms(2);
+        assert_eq!(ppms.len(), 3);
+        assert!(ppms[2].starts_with(b"P6\n8 6\n255\n"));
+        let gif = recording.to_gif(2, 5).unwrap();
+        assert!(gif.starts_with(b"GIF89a\x08\x00\x06\x00"));
+    }
+}
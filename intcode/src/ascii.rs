@@ -0,0 +1,166 @@
+use std::error::Error;
+use std::io::{BufRead, Write};
+
+use crate::{Vm, VmError};
+
+// Values outside this range aren't text; a program speaking ASCII uses them for its answer.
+const ASCII_MAX: i64 = 127;
+
+// Talks to a program that reads and writes lines of ASCII text.
+pub struct Console {
+    pub vm: Vm,
+    // Text output not yet read, including any unfinished line.
+    text: String,
+    // Every non-ASCII value the program has output, in order.
+    pub values: Vec<i64>
+}
+
+pub fn encode(text: &str) -> Vec<i64> {
+    text.chars().map(|c| c as i64).collect()
+}
+
+impl Console {
+    pub fn new(vm: Vm) -> Console {
+        Console { vm, text: String::new(), values: vec!() }
+    }
+
+    // Queues the text as-is.
+    pub fn send(&mut self, text: &str) {
+        for v in encode(text) {
+            self.vm.push_input(v);
+        }
+    }
+
+    // Queues the line followed by a newline.
+    pub fn send_line(&mut self, line: &str) {
+        self.send(line);
+        self.vm.push_input('\n' as i64);
+    }
+
+    pub fn send_lines(&mut self, lines: &[&str]) {
+        for line in lines {
+            self.send_line(line);
+        }
+    }
+
+    // Runs until the program halts or wants more input than has been sent.
+    pub fn run(&mut self) -> Result<(), VmError> {
+        self.vm.run()?;
+        for v in self.vm.outputs.drain(..) {
+            if (0..=ASCII_MAX).contains(&v) {
+                self.text.push(v as u8 as char);
+            } else {
+                self.values.push(v);
+            }
+        }
+        Ok(())
+    }
+
+    // The next complete line of output, without its newline.
+    pub fn read_line(&mut self) -> Option<String> {
+        let end = self.text.find('\n')?;
+        let line = self.text[..end].to_string();
+        self.text.drain(..=end);
+        Some(line)
+    }
+
+    // All the text output not yet read, finished line or not.
+    pub fn read_text(&mut self) -> String {
+        self.text.split_off(0)
+    }
+
+    // The program's answer: the last value it output that wasn't text.
+    pub fn result(&self) -> Option<i64> {
+        self.values.last().copied()
+    }
+
+    // Plays the program by hand: its text goes to `output` and each line read from `input` is
+    // sent whenever it waits for more. Stops when it halts or `input` runs dry.
+    pub fn interact<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> Result<(), Box<dyn Error>> {
+        loop {
+            self.run()?;
+            write!(output, "{}", self.read_text())?;
+            for v in self.values.drain(..) {
+                writeln!(output, "{}", v)?;
+            }
+            output.flush()?;
+            if self.vm.halted() {
+                return Ok(());
+            }
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            self.send_line(line.trim_end_matches(['\r', '\n'].as_ref()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    // Echoes each line it reads back in upper case until it reads an empty line, then outputs
+    // how many characters it saw in total.
+    fn shouter() -> Vec<i64> {
+        assemble("
+            loop:
+                IN [c]
+                EQ [c], #10, [t]
+                JNZ [t], #newline
+                ADD [count], #1, [count]
+                LT [c], #97, [t]
+                JNZ [t], #print
+                ADD [c], #-32, [c]
+            print:
+                OUT [c]
+                ADD #0, #0, [empty]
+                JNZ #1, #loop
+            newline:
+                JNZ [empty], #done
+                OUT #10
+                ADD #0, #1, [empty]
+                JNZ #1, #loop
+            done:
+                OUT [count]
+                HLT
+            c: db 0
+            t: db 0
+            count: db 1000
+            empty: db 1
+        ").unwrap()
+    }
+
+    #[test]
+    fn test_lines_and_result() {
+        let mut console = Console::new(Vm::new(shouter()));
+        console.send_line("hello");
+        console.run().unwrap();
+        assert_eq!(console.read_line(), Some("HELLO".to_string()));
+        assert_eq!(console.read_line(), None);
+        assert_eq!(console.result(), None);
+
+        console.send_lines(&["ab", "c", ""]);
+        console.run().unwrap();
+        assert!(console.vm.halted());
+        assert_eq!(console.read_text(), "AB\nC\n");
+        assert_eq!(console.result(), Some(1008));
+    }
+
+    #[test]
+    fn test_interact() {
+        let mut console = Console::new(Vm::new(shouter()));
+        let mut output = vec!();
+        console.interact("one\r\ntwo\n\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "ONE\nTWO\n1006\n");
+
+        // runs out of input before the program is done
+        let mut console = Console::new(Vm::new(shouter()));
+        let mut output = vec!();
+        console.interact("x".as_bytes(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "X\n");
+        assert!(console.vm.needs_input());
+    }
+}
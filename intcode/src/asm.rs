@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{argc, Mode, OP_ADD, OP_BASE, OP_HALT, OP_IN, OP_JNZ, OP_JZ, OP_MUL, OP_OUT, OP_WEQ, OP_WLT};
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+// A line of source after includes have been expanded, remembering where it came from.
+struct SourceLine {
+    file: String,
+    line: usize,
+    text: String
+}
+
+impl SourceLine {
+    fn error(&self, message: String) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.line, message }
+    }
+}
+
+// A number or label, plus or minus further numbers or labels.
+#[derive(Debug, Clone)]
+struct Expr {
+    terms: Vec<(i64, Term)>
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Number(i64),
+    Label(String)
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (sign, term)) in self.terms.iter().enumerate() {
+            match (i, *sign < 0) {
+                (0, false) => (),
+                (0, true) => write!(f, "-")?,
+                (_, false) => write!(f, " + ")?,
+                (_, true) => write!(f, " - ")?
+            }
+            match term {
+                Term::Number(n) => write!(f, "{}", n)?,
+                Term::Label(label) => write!(f, "{}", label)?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Operand {
+    mode: Mode,
+    value: Expr
+}
+
+enum Item {
+    Instruction { op: i64, operands: Vec<Operand> },
+    Data(Vec<Expr>)
+}
+
+pub fn op_for_mnemonic(mnemonic: &str) -> Option<i64> {
+    match mnemonic.to_ascii_uppercase().as_str() {
+        "ADD" => Some(OP_ADD),
+        "MUL" => Some(OP_MUL),
+        "IN" => Some(OP_IN),
+        "OUT" => Some(OP_OUT),
+        "JNZ" => Some(OP_JNZ),
+        "JZ" => Some(OP_JZ),
+        "LT" => Some(OP_WLT),
+        "EQ" => Some(OP_WEQ),
+        "ARB" => Some(OP_BASE),
+        "HLT" => Some(OP_HALT),
+        _ => None
+    }
+}
+
+fn mode_digit(mode: Mode) -> i64 {
+    match mode {
+        Mode::Normal => 0,
+        Mode::Imm => 1,
+        Mode::Base => 2
+    }
+}
+
+pub fn format_program(program: &[i64]) -> String {
+    let words: Vec<String> = program.iter().map(|w| w.to_string()).collect();
+    words.join(",")
+}
+
+// Assembles source text. Includes are resolved relative to the current directory.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+    let mut lines = vec!();
+    expand(source, "<input>", Path::new("."), 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+pub fn assemble_file(path: &Path) -> Result<Vec<i64>, AsmError> {
+    let mut lines = vec!();
+    include(path, &path.display().to_string(), 0, 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+fn include(path: &Path, from: &str, from_line: usize, depth: usize, lines: &mut Vec<SourceLine>) -> Result<(), AsmError> {
+    let error = |message: String| AsmError { file: from.to_string(), line: from_line, message };
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(error(format!("includes nested deeper than {}", MAX_INCLUDE_DEPTH)));
+    }
+    let source = fs::read_to_string(path)
+        .map_err(|e| error(format!("failed to read {}: {}", path.display(), e)))?;
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+    expand(&source, &path.display().to_string(), &dir, depth, lines)
+}
+
+fn expand(source: &str, file: &str, dir: &Path, depth: usize, lines: &mut Vec<SourceLine>) -> Result<(), AsmError> {
+    for (ix, text) in source.lines().enumerate() {
+        let text = strip_comment(text).trim();
+        if let Some(rest) = text.strip_prefix(".include") {
+            let name = rest.trim().trim_matches('"');
+            include(&dir.join(name), file, ix + 1, depth + 1, lines)?;
+        } else if !text.is_empty() {
+            lines.push(SourceLine { file: file.to_string(), line: ix + 1, text: text.to_string() });
+        }
+    }
+    Ok(())
+}
+
+// Comments run from ';' to the end of the line, except inside string literals.
+fn strip_comment(text: &str) -> &str {
+    let mut in_string = false;
+    for (ix, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &text[..ix],
+            _ => {}
+        }
+    }
+    text
+}
+
+fn assemble_lines(lines: &[SourceLine]) -> Result<Vec<i64>, AsmError> {
+    let mut labels: HashMap<String, i64> = HashMap::new();
+    let mut items: Vec<(&SourceLine, Item)> = vec!();
+    let mut address = 0;
+
+    for line in lines {
+        let mut text = line.text.as_str();
+        while let Some((label, rest)) = split_label(text) {
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(line.error(format!("label {} defined twice", label)));
+            }
+            text = rest;
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let item = parse_item(text).map_err(|message| line.error(message))?;
+        address += match &item {
+            Item::Instruction { operands, .. } => operands.len() as i64 + 1,
+            Item::Data(values) => values.len() as i64
+        };
+        items.push((line, item));
+    }
+
+    let mut program = vec!();
+    for (line, item) in items {
+        let resolve = |expr: &Expr| resolve(expr, &labels).map_err(|message| line.error(message));
+        match item {
+            Item::Instruction { op, operands } => {
+                let mut word = op;
+                let mut scale = 100;
+                for operand in &operands {
+                    word += mode_digit(operand.mode) * scale;
+                    scale *= 10;
+                }
+                program.push(word);
+                for operand in &operands {
+                    program.push(resolve(&operand.value)?);
+                }
+            },
+            Item::Data(values) => {
+                for value in &values {
+                    program.push(resolve(value)?);
+                }
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let colon = text.find(':')?;
+    let label = text[..colon].trim();
+    if is_identifier(label) {
+        Some((label, text[colon + 1..].trim()))
+    } else {
+        None
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {},
+        _ => return false
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_item(text: &str) -> Result<Item, String> {
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(ix) => (&text[..ix], text[ix..].trim()),
+        None => (text, "")
+    };
+
+    if mnemonic.eq_ignore_ascii_case("db") {
+        return parse_data(rest).map(Item::Data);
+    }
+
+    let op = op_for_mnemonic(mnemonic).ok_or(format!("unknown mnemonic {}", mnemonic))?;
+    let operands: Vec<Operand> = split_operands(rest).iter()
+        .map(|operand| parse_operand(operand))
+        .collect::<Result<_, _>>()?;
+
+    let expected = argc(op).expect("mnemonics map to real ops") as usize;
+    if operands.len() != expected {
+        return Err(format!("{} takes {} operands, found {}", mnemonic, expected, operands.len()));
+    }
+
+    Ok(Item::Instruction { op, operands })
+}
+
+fn split_operands(text: &str) -> Vec<&str> {
+    if text.is_empty() {
+        vec!()
+    } else {
+        text.split(',').map(str::trim).collect()
+    }
+}
+
+fn parse_data(text: &str) -> Result<Vec<Expr>, String> {
+    let mut values = vec!();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        if let Some(string) = rest.strip_prefix('"') {
+            let end = string.find('"').ok_or("unterminated string")?;
+            values.extend(unescape(&string[..end])?.chars()
+                .map(|c| Expr { terms: vec!((1, Term::Number(c as i64))) }));
+            rest = string[end + 1..].trim_start();
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            values.push(parse_expr(rest[..end].trim())?);
+            rest = &rest[end..];
+        }
+        rest = match rest.strip_prefix(',') {
+            Some(next) => next.trim_start(),
+            None if rest.is_empty() => rest,
+            None => return Err(format!("expected ',' before {}", rest))
+        };
+    }
+    Ok(values)
+}
+
+fn unescape(s: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        result.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('\\') => '\\',
+            Some('0') => '\0',
+            other => return Err(format!("bad escape \\{}", other.map(String::from).unwrap_or_default()))
+        });
+    }
+    Ok(result)
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    if let Some(imm) = text.strip_prefix('#') {
+        return Ok(Operand { mode: Mode::Imm, value: parse_expr(imm)? });
+    }
+
+    let inner = match text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        Some(inner) => inner.trim(),
+        None => text
+    };
+
+    if let Some(offset) = strip_base(inner) {
+        let value = if offset.is_empty() {
+            Expr { terms: vec!((1, Term::Number(0))) }
+        } else {
+            parse_expr(offset)?
+        };
+        Ok(Operand { mode: Mode::Base, value })
+    } else {
+        Ok(Operand { mode: Mode::Normal, value: parse_expr(inner)? })
+    }
+}
+
+// "rb+3" -> "+3", "rb" -> "", anything else isn't relative to the base.
+fn strip_base(text: &str) -> Option<&str> {
+    let rest = text.strip_prefix("rb")?;
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with('+') || rest.starts_with('-') {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    let mut terms = vec!();
+    let mut sign = 1;
+    let mut current = String::new();
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("missing value".to_string());
+    }
+
+    let mut push = |sign: i64, current: &mut String| -> Result<(), String> {
+        let token = current.trim();
+        if token.is_empty() {
+            return Err(format!("bad expression {}", text));
+        }
+        let term = if let Ok(n) = token.parse::<i64>() {
+            Term::Number(n)
+        } else if is_identifier(token) {
+            Term::Label(token.to_string())
+        } else {
+            return Err(format!("bad value {}", token));
+        };
+        terms.push((sign, term));
+        current.clear();
+        Ok(())
+    };
+
+    for c in text.chars() {
+        if (c == '+' || c == '-') && !current.trim().is_empty() {
+            push(sign, &mut current)?;
+            sign = if c == '-' { -1 } else { 1 };
+        } else if (c == '+' || c == '-') && current.trim().is_empty() {
+            if c == '-' {
+                sign = -sign;
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    push(sign, &mut current)?;
+
+    Ok(Expr { terms })
+}
+
+fn resolve(expr: &Expr, labels: &HashMap<String, i64>) -> Result<i64, String> {
+    let mut total: i64 = 0;
+    for (sign, term) in &expr.terms {
+        let value = match term {
+            Term::Number(n) => *n,
+            Term::Label(label) => *labels.get(label).ok_or(format!("undefined label {}", label))?
+        };
+        total = sign.checked_mul(value)
+            .and_then(|value| total.checked_add(value))
+            .ok_or(format!("{} overflows", expr))?;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::disassemble;
+    use crate::Vm;
+
+    #[test]
+    fn test_modes() {
+        let program = assemble("
+            ARB #5
+            IN [rb+0]
+            HLT
+            db 0
+        ").unwrap();
+        assert_eq!(program, vec!(109,5,203,0,99,0));
+
+        assert_eq!(assemble("add [4], #-2, [rb-3]").unwrap(), vec!(21001,4,-2,-3));
+        assert_eq!(assemble("out rb").unwrap(), vec!(204,0));
+        assert_eq!(assemble("out 7").unwrap(), vec!(4,7));
+    }
+
+    #[test]
+    fn test_labels() {
+        let source = "
+            ; count down from 3
+            start:  OUT [counter]
+                    ADD [counter], #-1, [counter]
+                    JNZ [counter], #start
+                    HLT
+            counter: db 3
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(program, vec!(4,10,1001,10,-1,10,1005,10,0,99,3));
+
+        let mut vm = Vm::new(program);
+        vm.run().unwrap();
+        assert_eq!(vm.outputs, vec!(3, 2, 1));
+    }
+
+    #[test]
+    fn test_label_arithmetic_and_strings() {
+        let program = assemble("
+            OUT [text+1]
+            HLT
+            text: db \"hi\\n\", end - text
+            end:
+        ").unwrap();
+        assert_eq!(program, vec!(4,4,99,104,105,10,4));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let quine = vec!(109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99);
+        let source: Vec<String> = disassemble(&quine, &[]).iter()
+            .map(|line| line.to_string().split_once(": ").unwrap().1.to_string())
+            .collect();
+        assert_eq!(assemble(&source.join("\n")).unwrap(), quine);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = assemble("nop").unwrap_err();
+        assert_eq!(error.to_string(), "<input>:1: unknown mnemonic nop");
+
+        let error = assemble("HLT\nJNZ #1").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "JNZ takes 2 operands, found 1");
+
+        let error = assemble("JNZ #1, #nowhere").unwrap_err();
+        assert_eq!(error.message, "undefined label nowhere");
+
+        let error = assemble("a: HLT\na: HLT").unwrap_err();
+        assert_eq!(error.message, "label a defined twice");
+
+        let error = assemble("db 9223372036854775807 + 1").unwrap_err();
+        assert_eq!(error.message, "9223372036854775807 + 1 overflows");
+
+        let error = assemble("HLT\nend: db -9223372036854775807 - end - 1").unwrap_err();
+        assert_eq!(error.message, "-9223372036854775807 - end - 1 overflows");
+        assert_eq!(assemble("db -9223372036854775807 - 1").unwrap(), vec!(i64::MIN));
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("intcode-asm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("lib.asm"), "double: MUL [rb+1], #2, [rb+1]\nJNZ #1, [rb+0]\n").unwrap();
+        fs::write(dir.join("main.asm"), "JNZ #1, #main\n.include \"lib.asm\"\nmain: HLT\n").unwrap();
+
+        let program = assemble_file(&dir.join("main.asm")).unwrap();
+        assert_eq!(program, vec!(1105,1,10,21202,1,2,1,2105,1,0,99));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
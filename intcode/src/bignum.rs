@@ -0,0 +1,346 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+
+use num::bigint::BigInt;
+use num::{ToPrimitive, Zero};
+
+use crate::{arg_modes, Arithmetic, Fault, Memory, Mode, Vm, VmError};
+use crate::{OP_ADD, OP_BASE, OP_HALT, OP_IN, OP_JNZ, OP_JZ, OP_MUL, OP_OUT, OP_WEQ, OP_WLT};
+
+// An Intcode machine whose words are arbitrary-precision integers. Addresses, the ip and the
+// relative base still have to fit in an i64. It's a plain interpreter, much slower than Vm, so
+// it's meant to take over from one when its arithmetic overflows rather than to start with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigVm {
+    pub ip: i64,
+    pub base: i64,
+    memory: HashMap<i64, BigInt>,
+    limit: usize,
+    pub fuel: Option<u64>,
+    pub inputs: VecDeque<BigInt>,
+    pub outputs: Vec<BigInt>
+}
+
+// How a machine run under bignum::run ended up.
+pub enum Precision {
+    Native(Box<Vm>),
+    Big(BigVm)
+}
+
+// Runs the machine. If its policy is Arithmetic::Promote, the first overflow moves it over to a
+// BigVm, which carries on from the overflowing instruction.
+pub fn run(mut vm: Vm) -> Result<Precision, VmError> {
+    match vm.run() {
+        Err(VmError::Overflow { .. }) if vm.arithmetic == Arithmetic::Promote => {
+            let mut big = BigVm::from(&vm);
+            big.run()?;
+            Ok(Precision::Big(big))
+        },
+        result => result.map(|_| Precision::Native(Box::new(vm)))
+    }
+}
+
+impl From<&Vm> for BigVm {
+    fn from(vm: &Vm) -> BigVm {
+        let mut memory = HashMap::new();
+        for (start, words) in vm.memory.pages() {
+            for (i, word) in words.iter().enumerate() {
+                if *word != 0 {
+                    memory.insert(start + i as i64, BigInt::from(*word));
+                }
+            }
+        }
+        BigVm {
+            ip: vm.ip,
+            base: vm.base,
+            memory,
+            limit: vm.memory.limit(),
+            fuel: vm.fuel,
+            inputs: vm.inputs.iter().map(|v| BigInt::from(*v)).collect(),
+            outputs: vm.outputs.iter().map(|v| BigInt::from(*v)).collect()
+        }
+    }
+}
+
+// Back to a native machine, which only works while every word still fits in an i64.
+impl TryFrom<&BigVm> for Vm {
+    type Error = VmError;
+
+    fn try_from(big: &BigVm) -> Result<Vm, VmError> {
+        let native = |word: &BigInt| word.to_i64().ok_or(VmError::Overflow { fault: big.fault() });
+
+        let mut vm = Vm::new(vec!());
+        vm.memory = Memory::with_limit(big.limit);
+        for (address, word) in &big.memory {
+            vm.memory.set(*address, native(word)?).map_err(|_| VmError::MemoryLimit { address: *address, limit: big.limit, fault: big.fault() })?;
+        }
+        vm.ip = big.ip;
+        vm.base = big.base;
+        vm.fuel = big.fuel;
+        vm.inputs = big.inputs.iter().map(native).collect::<Result<_, _>>()?;
+        vm.outputs = big.outputs.iter().map(native).collect::<Result<_, _>>()?;
+        Ok(vm)
+    }
+}
+
+impl BigVm {
+    pub fn new(program: Vec<BigInt>) -> BigVm {
+        let memory = program.into_iter().enumerate().map(|(i, word)| (i as i64, word)).collect();
+        BigVm { ip: 0, base: 0, memory, limit: crate::memory::DEFAULT_LIMIT, fuel: None, inputs: VecDeque::new(), outputs: vec!() }
+    }
+
+    pub fn push_input(&mut self, value: BigInt) {
+        self.inputs.push_back(value);
+    }
+
+    pub fn peek(&self, address: i64) -> BigInt {
+        self.memory.get(&address).cloned().unwrap_or_else(BigInt::zero)
+    }
+
+    pub fn halted(&self) -> bool {
+        self.peek(self.ip).to_i64().is_some_and(|word| word % 100 == OP_HALT)
+    }
+
+    pub fn needs_input(&self) -> bool {
+        self.peek(self.ip).to_i64().is_some_and(|word| word % 100 == OP_IN)
+    }
+
+    pub fn fault(&self) -> Fault {
+        // a word too big for an i64 can't be an instruction, and shows as 0
+        let word = self.peek(self.ip).to_i64().unwrap_or(0);
+        Fault { ip: self.ip, op: word % 100, word, base: self.base }
+    }
+
+    // Runs until the program halts or asks for input that hasn't been pushed yet.
+    pub fn run(&mut self) -> Result<(), VmError> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    fn address(&self, value: &BigInt) -> Result<i64, VmError> {
+        match value.to_i64() {
+            Some(address) if address < 0 => Err(VmError::NegativeAddress { address, fault: self.fault() }),
+            Some(address) => Ok(address),
+            None if *value < BigInt::zero() => Err(VmError::NegativeAddress { address: i64::MIN, fault: self.fault() }),
+            None => Err(VmError::MemoryLimit { address: i64::MAX, limit: self.limit, fault: self.fault() })
+        }
+    }
+
+    fn offset(&self, value: &BigInt) -> Result<i64, VmError> {
+        value.to_i64()
+            .and_then(|offset| self.base.checked_add(offset))
+            .ok_or(VmError::Overflow { fault: self.fault() })
+    }
+
+    fn param(&self, modes: &[Mode], i: usize) -> Result<BigInt, VmError> {
+        let raw = self.peek(self.ip + i as i64 + 1);
+        match modes[i] {
+            Mode::Imm => Ok(raw),
+            // like Vm, reads aren't held to the memory limit; nothing can have been written up
+            // where an address no longer fits in an i64, so it reads as 0
+            Mode::Normal => match self.address(&raw) {
+                Err(VmError::MemoryLimit { .. }) => Ok(BigInt::zero()),
+                address => Ok(self.peek(address?))
+            },
+            Mode::Base => {
+                let address = self.offset(&raw)?;
+                Ok(self.peek(self.address(&BigInt::from(address))?))
+            }
+        }
+    }
+
+    fn param_w(&self, modes: &[Mode], i: usize) -> Result<i64, VmError> {
+        let raw = self.peek(self.ip + i as i64 + 1);
+        match modes[i] {
+            Mode::Imm => Err(VmError::ImmediateWrite { fault: self.fault() }),
+            // checked when written, like Vm, so a machine can wait on IN to a bad address
+            Mode::Normal => raw.to_i64().map_or_else(|| self.address(&raw), Ok),
+            Mode::Base => self.offset(&raw)
+        }
+    }
+
+    fn write(&mut self, address: i64, value: BigInt) -> Result<(), VmError> {
+        if address < 0 {
+            return Err(VmError::NegativeAddress { address, fault: self.fault() });
+        }
+        if address as usize >= self.limit {
+            return Err(VmError::MemoryLimit { address, limit: self.limit, fault: self.fault() });
+        }
+        self.memory.insert(address, value);
+        Ok(())
+    }
+
+    pub fn step(&mut self) -> Result<bool, VmError> {
+        match self.fuel {
+            Some(0) => return Err(VmError::OutOfFuel { fault: self.fault() }),
+            Some(fuel) => self.fuel = Some(fuel - 1),
+            None => {}
+        }
+        if self.ip < 0 {
+            return Err(VmError::NegativeAddress { address: self.ip, fault: self.fault() });
+        }
+        let fault = self.fault();
+        let word = match self.peek(self.ip).to_i64() {
+            Some(word) => word,
+            None => return Err(VmError::UnknownOp { fault })
+        };
+        let op = word % 100;
+        let modes = arg_modes(op, word / 100).map_err(|e| VmError::decode(e, fault))?;
+        let next = self.ip + modes.len() as i64 + 1;
+
+        match op {
+            OP_ADD | OP_MUL | OP_WLT | OP_WEQ => {
+                let (a, b) = (self.param(&modes, 0)?, self.param(&modes, 1)?);
+                let address = self.param_w(&modes, 2)?;
+                let result = match op {
+                    OP_ADD => a + b,
+                    OP_MUL => a * b,
+                    OP_WLT => BigInt::from((a < b) as i64),
+                    _ => BigInt::from((a == b) as i64)
+                };
+                self.write(address, result)?;
+                self.ip = next;
+            },
+            OP_IN => {
+                let address = self.param_w(&modes, 0)?;
+                let input = match self.inputs.pop_front() {
+                    Some(input) => input,
+                    None => return Ok(false)
+                };
+                self.write(address, input)?;
+                self.ip = next;
+            },
+            OP_OUT => {
+                let value = self.param(&modes, 0)?;
+                self.outputs.push(value);
+                self.ip = next;
+            },
+            OP_JNZ | OP_JZ => {
+                let test = self.param(&modes, 0)?;
+                if test.is_zero() == (op == OP_JZ) {
+                    let target = self.param(&modes, 1)?;
+                    self.ip = target.to_i64().ok_or(VmError::Overflow { fault: self.fault() })?;
+                } else {
+                    self.ip = next;
+                }
+            },
+            OP_BASE => {
+                let offset = self.param(&modes, 0)?;
+                self.base = self.offset(&offset)?;
+                self.ip = next;
+            },
+            _ => return Ok(false)
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    // doubles 1 a hundred times
+    fn doubler() -> Vec<i64> {
+        assemble("
+            loop:
+                MUL [x], #2, [x]
+                ADD [n], #-1, [n]
+                JNZ [n], #loop
+                OUT [x]
+                HLT
+            x: db 1
+            n: db 100
+        ").unwrap()
+    }
+
+    fn two_to_the_100() -> BigInt {
+        "1267650600228229401496703205376".parse().unwrap()
+    }
+
+    #[test]
+    fn test_policies() {
+        assert_eq!(Vm::new(doubler()).arithmetic, Arithmetic::Wrap);
+
+        let mut vm = Vm::new(doubler());
+        vm.arithmetic = Arithmetic::Trap;
+        let error = vm.run().unwrap_err();
+        assert!(matches!(error, VmError::Overflow { fault } if fault.ip == 0));
+        assert_eq!(vm.ip, 0);
+
+        let mut vm = Vm::new(doubler());
+        vm.arithmetic = Arithmetic::Wrap;
+        vm.run().unwrap();
+        assert_eq!(vm.outputs, vec!(0));
+
+        let mut vm = Vm::new(vec!(1101, i64::MAX, 1, 5, 99, 0));
+        vm.arithmetic = Arithmetic::Wrap;
+        vm.run().unwrap();
+        assert_eq!(vm.peek(5), i64::MIN);
+    }
+
+    #[test]
+    fn test_promote() {
+        let mut vm = Vm::new(doubler());
+        vm.arithmetic = Arithmetic::Promote;
+        let big = match run(vm).unwrap() {
+            Precision::Big(big) => big,
+            Precision::Native(_) => panic!("should have been promoted")
+        };
+        assert!(big.halted());
+        assert_eq!(big.outputs, vec!(two_to_the_100()));
+
+        // nothing overflows, so it never leaves the native machine
+        let mut vm = Vm::new(vec!(1102,34915192,34915192,7,4,7,99,0));
+        vm.arithmetic = Arithmetic::Promote;
+        match run(vm).unwrap() {
+            Precision::Native(vm) => assert_eq!(vm.outputs, vec!(1219070632396864)),
+            Precision::Big(_) => panic!("shouldn't have been promoted")
+        }
+    }
+
+    #[test]
+    fn test_big_words() {
+        // reads a huge number, squares it and compares it against itself
+        let program = vec!(3,13,2,13,13,14,8,14,14,15,4,14,99,0,0,0);
+        let mut vm = BigVm::new(program.into_iter().map(BigInt::from).collect());
+        vm.push_input(two_to_the_100());
+        vm.run().unwrap();
+        assert_eq!(vm.outputs, vec!(two_to_the_100() * two_to_the_100()));
+        assert_eq!(vm.peek(15), BigInt::from(1));
+
+        let mut vm = BigVm::new(vec!(BigInt::from(4), two_to_the_100(), BigInt::from(99)));
+        vm.run().unwrap();
+        assert_eq!(vm.outputs, vec!(BigInt::zero()));
+        let mut vm = BigVm::new(vec!(BigInt::from(3), two_to_the_100(), BigInt::from(99)));
+        vm.push_input(BigInt::from(1));
+        assert!(matches!(vm.run().unwrap_err(), VmError::MemoryLimit { .. }));
+    }
+
+    #[test]
+    fn test_reads_past_an_i64_address() {
+        // overflows into the operand of the OUT, then reads through it
+        let mut vm = Vm::new(vec!(1102, 1 << 62, 2, 5, 4, 0, 99));
+        vm.arithmetic = Arithmetic::Promote;
+        match run(vm).unwrap() {
+            Precision::Big(big) => assert_eq!(big.outputs, vec!(BigInt::zero())),
+            Precision::Native(_) => panic!("should have been promoted")
+        }
+    }
+
+    #[test]
+    fn test_matches_vm() {
+        for case in crate::conformance::cases() {
+            let mut vm = BigVm::new(case.program.iter().map(|v| BigInt::from(*v)).collect());
+            vm.inputs = case.inputs.iter().map(|v| BigInt::from(*v)).collect();
+            let expected = crate::conformance::machine(&case.program, &case.inputs).run();
+            if let Err(VmError::Overflow { .. }) = expected {
+                continue;
+            }
+            match vm.run() {
+                Ok(()) => assert_eq!(Some(vm.outputs), case.outputs.map(|o| o.into_iter().map(BigInt::from).collect()), "{}", case.name),
+                Err(e) => assert_eq!(Err(e), expected, "{}", case.name)
+            }
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use std::env;
+use std::fs;
+use std::io;
+
+use intcode::ascii::Console;
+use intcode::{parse_program, Vm};
+
+// usage: ascii [program file] [script file]
+// Lines from the script are sent first, then play continues from stdin.
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = args.first().map(String::as_str).unwrap_or("src/input");
+
+    let input = fs::read_to_string(path).expect("failed to read program");
+    let program = parse_program(&input).expect("failed to parse program");
+    let mut console = Console::new(Vm::new(program));
+
+    if let Some(script) = args.get(1) {
+        let script = fs::read_to_string(script).expect("failed to read script");
+        for line in script.lines() {
+            console.send_line(line);
+        }
+    }
+
+    let stdin = io::stdin();
+    console.interact(stdin.lock(), io::stdout()).expect("console error");
+}
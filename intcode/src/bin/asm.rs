@@ -0,0 +1,24 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use intcode::asm::{assemble_file, format_program};
+
+// usage: asm <source file> [output file]
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let source = args.first().expect("usage: asm <source file> [output file]");
+
+    let program = match assemble_file(Path::new(source)) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match args.get(1) {
+        Some(output) => fs::write(output, format_program(&program)).expect("failed to write program"),
+        None => println!("{}", format_program(&program))
+    }
+}
@@ -0,0 +1,123 @@
+use std::env;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use intcode::asm::assemble;
+use intcode::cache::DecodeCache;
+use intcode::{parse_program, IoDevice, Storage, Vm};
+
+const RUNS: usize = 3;
+
+const LOOP: &str = "
+    ARB #2000
+    IN [rb+3]
+loop:
+    ADD [rb+0], #1, [rb+0]
+    ADD [rb+1], [rb+0], [rb+1]
+    LT [rb+0], [rb+3], [rb+2]
+    JNZ [rb+2], #loop
+    OUT [rb+1]
+    HLT
+";
+
+// Holds the joystick still; the game still runs until the ball is lost.
+struct Joystick;
+
+impl IoDevice for Joystick {
+    fn input(&mut self) -> Option<i64> {
+        Some(0)
+    }
+
+    fn output(&mut self, _: i64) {}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Engine {
+    Interpreter,
+    Cached,
+    Compiled
+}
+
+const ENGINES: [Engine; 3] = [Engine::Interpreter, Engine::Cached, Engine::Compiled];
+
+fn vm(program: &[i64], engine: Engine) -> Vm {
+    let mut vm = Vm::new(program.to_vec());
+    match engine {
+        Engine::Interpreter => {},
+        Engine::Cached => vm.cache = Some(DecodeCache::new()),
+        Engine::Compiled => { vm.compile(); }
+    }
+    vm
+}
+
+// day 9 part 2
+fn boost(program: &[i64], engine: Engine) {
+    let mut vm = vm(program, engine);
+    vm.push_input(2);
+    vm.run().expect("vm error");
+}
+
+// day 13 part 2
+fn arcade(program: &[i64], engine: Engine) {
+    // quarters in the slot
+    let mut program = program.to_vec();
+    program[0] = 2;
+    let mut vm = vm(&program, engine);
+    vm.run_with(&mut Joystick).expect("vm error");
+}
+
+// day 19 part 1: one fresh machine per probe of the 50x50 grid
+fn tractor(program: &[i64], engine: Engine) {
+    let mut vm = vm(program, engine);
+    let fresh = vm.snapshot();
+    for y in 0..50 {
+        for x in 0..50 {
+            vm.restore(&fresh);
+            vm.push_input(x);
+            vm.push_input(y);
+            vm.run().expect("vm error");
+        }
+    }
+}
+
+fn best_of(f: impl Fn()) -> Duration {
+    (0..RUNS).map(|_| {
+        let start = Instant::now();
+        f();
+        start.elapsed()
+    }).min().unwrap()
+}
+
+// usage: bench boost|arcade|tractor <program file>
+//        bench [loop [iterations]]
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let workload = args.first().map(String::as_str).unwrap_or("loop");
+
+    let load = || -> Storage {
+        let path = args.get(1).expect("missing program file");
+        parse_program(&fs::read_to_string(path).expect("failed to read program")).expect("failed to parse program")
+    };
+
+    let run: Box<dyn Fn(Engine)> = match workload {
+        "boost" => { let program = load(); Box::new(move |engine| boost(&program, engine)) },
+        "arcade" => { let program = load(); Box::new(move |engine| arcade(&program, engine)) },
+        "tractor" => { let program = load(); Box::new(move |engine| tractor(&program, engine)) },
+        "loop" => {
+            let iterations = args.get(1).map_or(3_000_000, |n| n.parse::<i64>().expect("iterations must be a number"));
+            let program = assemble(LOOP).expect("loop assembles");
+            Box::new(move |engine| {
+                let mut vm = vm(&program, engine);
+                vm.push_input(iterations);
+                vm.run().expect("vm error");
+            })
+        },
+        other => panic!("unknown workload {}", other)
+    };
+
+    let times: Vec<Duration> = ENGINES.iter().map(|engine| best_of(|| run(*engine))).collect();
+    let summary: Vec<String> = ENGINES.iter().zip(times.iter())
+        .map(|(engine, time)| format!("{:?} {:?} ({:.2}x)", engine, time, times[0].as_secs_f64() / time.as_secs_f64()))
+        .collect();
+    println!("{}: {}", workload, summary.join(", "));
+}
@@ -0,0 +1,36 @@
+use std::env;
+use std::fs;
+
+use intcode::cfg::analyse;
+use intcode::parse_program;
+
+// usage: cfg [--dot] [program file] [extra entry points...]
+// Lists the basic blocks and data ranges of a program, or prints its graph for Graphviz.
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let dot = args.first().is_some_and(|arg| arg == "--dot");
+    if dot {
+        args.remove(0);
+    }
+    let path = args.first().map(String::as_str).unwrap_or("src/input");
+    let mut roots = vec!(0);
+    roots.extend(args.iter().skip(1).map(|arg| arg.parse::<i64>().expect("entry points must be addresses")));
+
+    let input = fs::read_to_string(path).expect("failed to read program");
+    let program = parse_program(&input).expect("failed to parse program");
+    let graph = analyse(&program, &roots);
+
+    if dot {
+        print!("{}", graph.to_dot());
+        return;
+    }
+    for block in graph.blocks.values() {
+        let reached = if graph.reachable.contains(&block.start) { "" } else { " (unreached)" };
+        let targets: Vec<String> = block.successors.iter().map(|(target, edge)| format!("{}:{:?}", target, edge)).collect();
+        let indirect = if block.indirect { " ?" } else { "" };
+        println!("block {}..{}{} -> [{}]{}", block.start, block.end, reached, targets.join(", "), indirect);
+    }
+    for (start, end) in graph.data() {
+        println!("data {}..{}", start, end);
+    }
+}
@@ -0,0 +1,41 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use intcode::debugger::Debugger;
+use intcode::{parse_program, Vm};
+
+// usage: debug [program file] [inputs...]
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = args.first().map(String::as_str).unwrap_or("src/input");
+    let inputs: Vec<i64> = args.iter().skip(1)
+        .map(|arg| arg.parse::<i64>().expect("inputs must be numbers"))
+        .collect();
+
+    let input = fs::read_to_string(path).expect("failed to read program");
+    let program = parse_program(&input).expect("failed to parse program");
+    let mut debugger = Debugger::new(Vm::with_inputs(program, inputs));
+
+    println!("{}", debugger.describe_next());
+    let stdin = io::stdin();
+    let mut last = String::from("help");
+    loop {
+        print!("(icdb) ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).expect("failed to read command") == 0 {
+            break;
+        }
+        // an empty line repeats the previous command, handy for stepping
+        if !line.trim().is_empty() {
+            last = line.trim().to_string();
+        }
+
+        match debugger.command(&last) {
+            Some(output) => print!("{}", output),
+            None => break
+        }
+    }
+}
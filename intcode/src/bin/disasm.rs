@@ -0,0 +1,21 @@
+use std::env;
+use std::fs;
+
+use intcode::disasm::disassemble;
+use intcode::parse_program;
+
+// usage: disasm [program file] [extra entry points...]
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = args.first().map(String::as_str).unwrap_or("src/input");
+    let roots: Vec<i64> = args.iter().skip(1)
+        .map(|arg| arg.parse::<i64>().expect("entry points must be addresses"))
+        .collect();
+
+    let input = fs::read_to_string(path).expect("failed to read program");
+    let program = parse_program(&input).expect("failed to parse program");
+
+    for line in disassemble(&program, &roots) {
+        println!("{}", line);
+    }
+}
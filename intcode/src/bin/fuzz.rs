@@ -0,0 +1,26 @@
+use std::env;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use intcode::fuzz::Fuzzer;
+
+// usage: fuzz [iterations] [seed]
+// Runs random programs on every backend and prints any disagreement, panic or hang found.
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let iterations = args.first().map_or(10_000, |n| n.parse::<u64>().expect("iterations must be a number"));
+    let seed = args.get(1).map_or_else(
+        || SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        |n| n.parse::<u64>().expect("seed must be a number"));
+
+    let mut fuzzer = Fuzzer::new(seed);
+    fuzzer.run(iterations);
+    for report in &fuzzer.findings {
+        println!("{}\n", report);
+    }
+    println!("seed {}: {} programs, {} features, {} kept, {} findings",
+             seed, fuzzer.executions, fuzzer.coverage.len(), fuzzer.corpus.len(), fuzzer.findings.len());
+    if !fuzzer.findings.is_empty() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,70 @@
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use display::canvas;
+use intcode::arcade::{Player, Recording};
+
+const USAGE: &str = "usage: replay <recording> [--fps n] [--from frame] [--to frame] [--gif file] [--ppm directory]";
+
+// Plays an arcade recording in the terminal, or with --gif or --ppm exports the frames instead.
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        eprintln!("{}", USAGE);
+        return;
+    }
+    let option = |name: &str| args.iter().position(|arg| arg == name).map(|i| args.get(i + 1).expect("option is missing its value"));
+    let number = |name: &str, default: usize| option(name).map_or(default, |n| n.parse::<usize>().expect("options must be numbers"));
+
+    let fps = match u32::try_from(number("--fps", 30)) {
+        Ok(fps) => fps,
+        Err(_) => {
+            eprintln!("{}", USAGE);
+            return;
+        }
+    };
+
+    let recording = Recording::load(Path::new(&args[0])).expect("failed to load recording");
+    if recording.frames.is_empty() {
+        println!("no frames");
+        return;
+    }
+    let last = recording.frames.len() - 1;
+    let from = number("--from", 0).min(last);
+    let to = number("--to", last).min(last);
+
+    if option("--gif").is_some() || option("--ppm").is_some() {
+        let pictures = recording.pictures();
+        let pictures = pictures.get(from..=to).unwrap_or_default();
+        if let Some(path) = option("--gif") {
+            let delay = 100u32.checked_div(fps).map_or(0, |delay| delay.max(1) as u16);
+            let gif = canvas::gif(pictures, 4, delay).expect("failed to make gif");
+            fs::write(path, gif).expect("failed to write gif");
+        }
+        if let Some(directory) = option("--ppm") {
+            fs::create_dir_all(directory).expect("failed to create directory");
+            for (i, picture) in pictures.iter().enumerate() {
+                let path = Path::new(directory).join(format!("frame{:05}.ppm", from + i));
+                fs::write(path, canvas::ppm(picture, 4)).expect("failed to write ppm");
+            }
+        }
+        return;
+    }
+
+    let mut player = Player::new(&recording);
+    player.seek(from);
+    loop {
+        // clear the terminal and home the cursor
+        println!("\x1b[2J\x1b[H{}frame {}/{}", player.screen, player.position - 1, last);
+        if player.position > to || !player.step() {
+            break;
+        }
+        if fps > 0 {
+            thread::sleep(Duration::from_secs(1) / fps);
+        }
+    }
+}
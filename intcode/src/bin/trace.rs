@@ -0,0 +1,75 @@
+use std::env;
+use std::fs::{self, File};
+use std::process;
+
+use intcode::trace::{first_divergence, read_binary, BinaryTracer, JsonTracer, Tracer};
+use intcode::{parse_program, Vm};
+
+const USAGE: &str = "usage: trace <program file> <trace file> [inputs...] | trace --diff <a> <b>";
+
+// usage: trace <program file> <trace file> [inputs...]
+//        trace --diff <binary trace> <binary trace>
+// Traces are written as JSON lines when the file name ends in .jsonl, otherwise in binary.
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    if args.first().is_some_and(|arg| arg == "--diff") {
+        if args.len() < 3 {
+            return Err(USAGE.to_string());
+        }
+        return diff(&args[1], &args[2]);
+    }
+    if args.len() < 2 {
+        return Err(USAGE.to_string());
+    }
+
+    let input = fs::read_to_string(&args[0]).map_err(|e| format!("failed to read {}: {}", args[0], e))?;
+    let program = parse_program(&input).map_err(|e| format!("failed to parse {}: {}", args[0], e))?;
+    let inputs = args.iter().skip(2)
+        .map(|arg| arg.parse::<i64>().map_err(|_| format!("inputs must be numbers, not {}", arg)))
+        .collect::<Result<Vec<i64>, String>>()?;
+
+    let file = File::create(&args[1]).map_err(|e| format!("failed to create {}: {}", args[1], e))?;
+    let tracer: Box<dyn Tracer> = if args[1].ends_with(".jsonl") {
+        Box::new(JsonTracer::new(file))
+    } else {
+        Box::new(BinaryTracer::new(file))
+    };
+
+    let mut vm = Vm::with_inputs(program, inputs);
+    vm.tracer = Some(tracer);
+    let result = vm.run();
+    vm.tracer.as_mut().unwrap().finish().map_err(|e| format!("failed to write trace: {}", e))?;
+
+    result.map_err(|e| format!("vm error: {}", e))?;
+    println!("output: {:?}", vm.outputs);
+    Ok(())
+}
+
+fn diff(a: &str, b: &str) -> Result<(), String> {
+    let read = |path: &str| {
+        let file = File::open(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+        read_binary(file).map_err(|e| format!("failed to read {}: {}", path, e))
+    };
+    let (a, b) = (read(a)?, read(b)?);
+
+    match first_divergence(&a, &b) {
+        None => println!("traces match ({} steps)", a.len()),
+        Some(step) => {
+            println!("traces diverge at step {}", step);
+            for (name, trace) in [("a", &a), ("b", &b)].iter() {
+                match trace.get(step) {
+                    Some(record) => println!("{}: {}", name, record.to_json(step as u64)),
+                    None => println!("{}: <ended>", name)
+                }
+            }
+        }
+    }
+    Ok(())
+}
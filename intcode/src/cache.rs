@@ -0,0 +1,143 @@
+use crate::Instruction;
+use crate::memory::{PAGE_BITS, PAGE_SIZE};
+
+// The most words an instruction can span, so the furthest back a write can land inside one.
+const MAX_SIZE: i64 = 4;
+
+// Decoded instructions keyed by the address they start at. Anything that writes memory behind
+// the VM's back has to call invalidate (or clear) so self-modifying code is seen. Entries are
+// paged like Memory, so code far up in memory only costs the pages it runs in.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeCache {
+    pages: Vec<Option<Box<[Option<Instruction>]>>>,
+    pub hits: u64,
+    pub misses: u64
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        DecodeCache::default()
+    }
+
+    #[inline]
+    pub fn get(&mut self, ip: i64) -> Option<Instruction> {
+        let found = self.entry(ip).copied().flatten();
+        if found.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        found
+    }
+
+    pub fn insert(&mut self, ip: i64, instruction: Instruction) {
+        if ip < 0 {
+            return;
+        }
+        let ip = ip as usize;
+        let page = ip >> PAGE_BITS;
+        if page >= self.pages.len() {
+            self.pages.resize_with(page + 1, || None);
+        }
+        let page = self.pages[page].get_or_insert_with(|| vec![None; PAGE_SIZE].into_boxed_slice());
+        page[ip % PAGE_SIZE] = Some(instruction);
+    }
+
+    fn entry(&self, ip: i64) -> Option<&Option<Instruction>> {
+        if ip < 0 {
+            return None;
+        }
+        let ip = ip as usize;
+        self.pages.get(ip >> PAGE_BITS)?.as_ref().map(|page| &page[ip % PAGE_SIZE])
+    }
+
+    // Drops every cached instruction that includes the written address.
+    #[inline]
+    pub fn invalidate(&mut self, address: i64) {
+        let first = address.saturating_sub(MAX_SIZE - 1).max(0);
+        for ip in first..=address {
+            if self.entry(ip).copied().flatten().is_some_and(|i| ip + i.size() > address) {
+                let ip = ip as usize;
+                if let Some(Some(page)) = self.pages.get_mut(ip >> PAGE_BITS) {
+                    page[ip % PAGE_SIZE] = None;
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+
+    pub fn pages_allocated(&self) -> usize {
+        self.pages.iter().filter(|page| page.is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory, Vm};
+
+    fn cached(program: Vec<i64>, inputs: Vec<i64>) -> Vm {
+        let mut vm = Vm::with_inputs(program, inputs);
+        vm.cache = Some(DecodeCache::new());
+        vm.run().unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_loop_hits_cache() {
+        // count [20] down from 3, outputting each value
+        let vm = cached(vec!(1101,3,0,20,4,20,1001,20,-1,20,1005,20,4,99), vec!());
+        assert_eq!(vm.outputs, vec!(3, 2, 1));
+        let cache = vm.cache.unwrap();
+        assert_eq!(cache.misses, 5);
+        assert_eq!(cache.hits, 6);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // loops twice; the first pass rewrites the OUT's operand to output the ADD's result
+        let program = vec!(1101,7,8,30,4,29,1101,30,0,5,1001,31,1,31,1008,31,2,32,1006,32,0,99);
+        let mut plain = Vm::new(program.clone());
+        plain.run().unwrap();
+        let vm = cached(program, vec!());
+        assert_eq!(vm.outputs, plain.outputs);
+        assert_eq!(vm.outputs, vec!(0, 15));
+    }
+
+    #[test]
+    fn test_invalidate_covers_operands() {
+        let program = [1101,1,2,3,1001,4,5,6,104,7,99];
+        let mut cache = DecodeCache::new();
+        for ip in [0, 4, 8, 10].iter() {
+            cache.insert(*ip, crate::disasm::decode_at(&program, *ip).unwrap());
+        }
+
+        let cached = |cache: &mut DecodeCache| [0, 4, 8, 10].iter().map(|ip| cache.get(*ip).is_some()).collect::<Vec<bool>>();
+        cache.invalidate(6);
+        assert_eq!(cached(&mut cache), vec!(true, false, true, true));
+        cache.invalidate(3);
+        cache.invalidate(10);
+        assert_eq!(cached(&mut cache), vec!(false, false, true, false));
+        cache.invalidate(1000);
+        cache.invalidate(-2);
+        cache.invalidate(i64::MIN);
+        cache.invalidate(i64::MAX);
+    }
+
+    #[test]
+    fn test_high_code_is_paged() {
+        // jump most of the way up memory and run a few instructions there
+        let top = (memory::DEFAULT_LIMIT - 8) as i64;
+        let mut vm = Vm::new(vec!(1106,0,top));
+        vm.memory.set(top, 104).unwrap();
+        vm.memory.set(top + 1, 5).unwrap();
+        vm.memory.set(top + 2, 99).unwrap();
+        vm.cache = Some(DecodeCache::new());
+        vm.run().unwrap();
+        assert_eq!(vm.outputs, vec!(5));
+        assert_eq!(vm.cache.unwrap().pages_allocated(), 2);
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::disasm::{code_pointers, code_starts, decode_at, successors};
+use crate::{Instruction, Mode};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Edge {
+    // Running off the end of the block into the next one.
+    Fall,
+    // A jump with an immediate target.
+    Jump,
+    // An immediate copied into memory that looks like a code address (a pushed return address,
+    // say), so control probably gets there later through an indirect jump.
+    Pointer
+}
+
+// A straight run of instructions, only entered at the top and only left at the bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: i64,
+    // One past the last word of the last instruction.
+    pub end: i64,
+    pub instructions: Vec<(i64, Instruction)>,
+    pub successors: Vec<(i64, Edge)>,
+    // Ends in a jump whose target is read from memory, so where it goes isn't known.
+    pub indirect: bool
+}
+
+// The control-flow graph of a program image, as far as it can be followed statically. Code
+// that rewrites itself, or that is only reached through indirect jumps with no pointer to it
+// in the image, won't be found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Graph {
+    pub blocks: BTreeMap<i64, Block>,
+    pub roots: Vec<i64>,
+    // Blocks reachable from the roots by following jumps and fall through, without guessing.
+    pub reachable: BTreeSet<i64>,
+    len: usize
+}
+
+fn is_jump(instruction: &Instruction) -> bool {
+    matches!(instruction, Instruction::Jnz { .. } | Instruction::Jz { .. } | Instruction::Halt)
+}
+
+fn is_indirect(instruction: &Instruction) -> bool {
+    let target_unknown = |test: &crate::Arg, target: &crate::Arg, jump_if_zero: bool| {
+        let never = test.mode == Mode::Imm && (test.value == 0) != jump_if_zero;
+        target.mode != Mode::Imm && !never
+    };
+    match instruction {
+        Instruction::Jnz { test, abs_target } => target_unknown(test, abs_target, false),
+        Instruction::Jz { test, abs_target } => target_unknown(test, abs_target, true),
+        _ => false
+    }
+}
+
+pub fn analyse(program: &[i64], roots: &[i64]) -> Graph {
+    let starts = code_starts(program, roots);
+
+    // a block starts at every root and jump target, after every jump, and wherever code
+    // starts that isn't run into from the instruction before
+    let mut leaders: BTreeSet<i64> = roots.iter().copied().filter(|root| starts.get(*root as usize) == Some(&true)).collect();
+    let mut falls_into = vec![false; program.len() + 1];
+    for (address, _) in starts.iter().enumerate().filter(|(_, start)| **start) {
+        let address = address as i64;
+        let instruction = decode_at(program, address).expect("code start decodes");
+        let next = address + instruction.size();
+        if is_jump(&instruction) {
+            leaders.extend(successors(address, &instruction));
+            leaders.insert(next);
+        } else {
+            falls_into[next as usize] = true;
+        }
+        leaders.extend(code_pointers(&instruction));
+    }
+    for (address, _) in starts.iter().enumerate().filter(|(_, start)| **start) {
+        if !falls_into[address] {
+            leaders.insert(address as i64);
+        }
+    }
+    leaders.retain(|address| starts.get(*address as usize) == Some(&true));
+
+    let mut blocks = BTreeMap::new();
+    for start in &leaders {
+        let mut block = Block { start: *start, end: *start, instructions: vec!(), successors: vec!(), indirect: false };
+        loop {
+            let instruction = decode_at(program, block.end).expect("code start decodes");
+            block.instructions.push((block.end, instruction));
+            for pointer in code_pointers(&instruction) {
+                if starts.get(pointer as usize) == Some(&true) {
+                    block.successors.push((pointer, Edge::Pointer));
+                }
+            }
+            let next = block.end + instruction.size();
+            block.end = next;
+
+            if is_jump(&instruction) {
+                block.indirect = is_indirect(&instruction);
+                for target in successors(block.end - instruction.size(), &instruction) {
+                    block.successors.push((target, if target == next { Edge::Fall } else { Edge::Jump }));
+                }
+                break;
+            }
+            if leaders.contains(&next) || starts.get(next as usize) != Some(&true) {
+                if starts.get(next as usize) == Some(&true) {
+                    block.successors.push((next, Edge::Fall));
+                }
+                break;
+            }
+        }
+        block.successors.sort();
+        block.successors.dedup();
+        blocks.insert(*start, block);
+    }
+
+    let mut reachable = BTreeSet::new();
+    let mut pending: Vec<i64> = roots.iter().copied().filter(|root| blocks.contains_key(root)).collect();
+    while let Some(start) = pending.pop() {
+        if !reachable.insert(start) {
+            continue;
+        }
+        pending.extend(blocks[&start].successors.iter()
+            .filter(|(target, edge)| *edge != Edge::Pointer && blocks.contains_key(target))
+            .map(|(target, _)| *target));
+    }
+
+    Graph { blocks, roots: roots.to_vec(), reachable, len: program.len() }
+}
+
+impl Graph {
+    // Whether the word at address belongs to an instruction in some block.
+    pub fn is_code(&self, address: i64) -> bool {
+        self.blocks.range(..=address).next_back().is_some_and(|(_, block)| address < block.end)
+    }
+
+    // The runs of words that aren't code, as (start, end) pairs with end exclusive. Tables and
+    // variables live here.
+    pub fn data(&self) -> Vec<(i64, i64)> {
+        let mut ranges = vec!();
+        let mut address = 0;
+        for block in self.blocks.values() {
+            if block.start > address {
+                ranges.push((address, block.start));
+            }
+            address = address.max(block.end);
+        }
+        if (address as usize) < self.len {
+            ranges.push((address, self.len as i64));
+        }
+        ranges
+    }
+
+    pub fn indirect(&self) -> Vec<i64> {
+        self.blocks.values()
+            .filter(|block| block.indirect)
+            .map(|block| block.instructions.last().unwrap().0)
+            .collect()
+    }
+
+    // Graphviz source. Unreachable blocks are grey, indirect jumps point at a "?" node and
+    // pointer edges are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph intcode {\n    node [shape=box, fontname=monospace];\n");
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (address, instruction) in &block.instructions {
+                write!(label, "{:>5}: {}\\l", address, instruction).unwrap();
+            }
+            let style = if self.reachable.contains(&block.start) { "" } else { ", style=filled, fillcolor=lightgrey" };
+            writeln!(dot, "    b{} [label=\"{}\"{}];", block.start, label, style).unwrap();
+        }
+        for block in self.blocks.values() {
+            for (target, edge) in &block.successors {
+                let style = match edge {
+                    Edge::Fall => "",
+                    Edge::Jump => " [color=blue]",
+                    Edge::Pointer => " [style=dashed]"
+                };
+                writeln!(dot, "    b{} -> b{}{};", block.start, target, style).unwrap();
+            }
+            if block.indirect {
+                writeln!(dot, "    b{} -> unknown [color=red];", block.start).unwrap();
+            }
+        }
+        if self.blocks.values().any(|block| block.indirect) {
+            dot.push_str("    unknown [label=\"?\", shape=circle];\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    fn starts(graph: &Graph) -> Vec<i64> {
+        graph.blocks.keys().copied().collect()
+    }
+
+    #[test]
+    fn test_diamond() {
+        let program = assemble("
+                IN [x]
+                JZ [x], #zero
+                OUT #1
+                JNZ #1, #done
+            zero:
+                OUT #0
+            done:
+                HLT
+            x: db 0
+        ").unwrap();
+        let graph = analyse(&program, &[0]);
+        assert_eq!(starts(&graph), vec!(0, 5, 10, 12));
+        assert_eq!(graph.blocks[&0].successors, vec!((5, Edge::Fall), (10, Edge::Jump)));
+        assert_eq!(graph.blocks[&5].successors, vec!((12, Edge::Jump)));
+        assert_eq!(graph.blocks[&10].successors, vec!((12, Edge::Fall)));
+        assert!(graph.blocks[&12].successors.is_empty());
+        assert_eq!(graph.reachable.len(), 4);
+        assert_eq!(graph.data(), vec!((13, 14)));
+        assert!(graph.indirect().is_empty());
+    }
+
+    #[test]
+    fn test_call_and_return() {
+        // call the function at 9 with return address 7, which halts; the function returns
+        // through [rb+0], which can't be followed
+        let program = [21101,7,0,1,1105,1,9,99,0,109,1,2105,1,0];
+        let graph = analyse(&program, &[0]);
+        assert_eq!(starts(&graph), vec!(0, 7, 9));
+        assert_eq!(graph.blocks[&0].successors, vec!((7, Edge::Pointer), (9, Edge::Jump)));
+        assert_eq!(graph.indirect(), vec!(11));
+        // 7 is only reached through the pushed return address
+        assert_eq!(graph.reachable, vec!(0, 9).into_iter().collect());
+        assert!(graph.is_code(12));
+        assert!(!graph.is_code(8));
+        assert_eq!(graph.data(), vec!((8, 9)));
+    }
+
+    #[test]
+    fn test_dead_branch_and_dot() {
+        // JZ #1 never jumps, so 6 is data unless something else reaches it
+        let program = [1106,1,6,104,5,99,104,6,99];
+        let graph = analyse(&program, &[0]);
+        assert_eq!(starts(&graph), vec!(0, 3));
+        assert_eq!(graph.data(), vec!((6, 9)));
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph intcode {"));
+        assert!(dot.contains("    b0 [label=\"    0: JZ   #1, #6\\l\"];"));
+        assert!(dot.contains("    b0 -> b3;"));
+        assert!(!dot.contains("unknown"));
+
+        let graph = analyse(&program, &[0, 6]);
+        assert!(graph.reachable.contains(&6));
+        let dot = analyse(&[6,0,5,99], &[0]).to_dot();
+        assert!(dot.contains("b0 -> unknown"));
+    }
+}
@@ -0,0 +1,292 @@
+use std::sync::Arc;
+
+use crate::disasm::{code_starts, decode_at};
+use crate::{Arg, Instruction, IoDevice, Memory, Mode, Vm, VmError};
+
+// One translated instruction. It behaves exactly like Vm::execute on the same instruction:
+// false means halted or waiting for input, with the ip left where the interpreter would leave it.
+type Op = Box<dyn Fn(&mut Vm, &mut dyn IoDevice) -> Result<bool, VmError> + Send + Sync>;
+
+#[derive(Debug, Copy, Clone)]
+enum Operand {
+    Imm(i64),
+    Pos(i64),
+    Rel(i64)
+}
+
+impl Operand {
+    fn new(arg: Arg) -> Operand {
+        match arg.mode {
+            Mode::Imm => Operand::Imm(arg.value),
+            Mode::Normal => Operand::Pos(arg.value),
+            Mode::Base => Operand::Rel(arg.value)
+        }
+    }
+
+    #[inline]
+    fn get(self, vm: &Vm) -> Result<i64, VmError> {
+        match self {
+            Operand::Imm(v) => Ok(v),
+            Operand::Pos(address) => vm.read(address),
+            Operand::Rel(offset) => vm.read(vm.add(vm.base, offset)?)
+        }
+    }
+
+    #[inline]
+    fn address(self, vm: &Vm) -> Result<i64, VmError> {
+        match self {
+            Operand::Imm(_) => Err(VmError::ImmediateWrite { fault: vm.fault() }),
+            Operand::Pos(address) => Ok(address),
+            Operand::Rel(offset) => vm.add(vm.base, offset)
+        }
+    }
+}
+
+// A program image translated ahead of time into a table of closures, one per reachable
+// instruction. Anything the translation didn't reach is left to the interpreter.
+pub struct Compiled {
+    image: Vec<i64>,
+    ops: Vec<Option<Op>>,
+    covered: Vec<bool>
+}
+
+fn binary(a: Arg, b: Arg, dest: Arg, next: i64, f: fn(&Vm, i64, i64) -> Result<i64, VmError>) -> Op {
+    let (a, b, dest) = (Operand::new(a), Operand::new(b), Operand::new(dest));
+    Box::new(move |vm, _| {
+        let result = f(vm, a.get(vm)?, b.get(vm)?)?;
+        let address = dest.address(vm)?;
+        vm.write(address, result)?;
+        vm.ip = next;
+        Ok(true)
+    })
+}
+
+fn branch(test: Arg, target: Arg, next: i64, jump_if_zero: bool) -> Op {
+    let (test, target) = (Operand::new(test), Operand::new(target));
+    Box::new(move |vm, _| {
+        vm.ip = if (test.get(vm)? == 0) == jump_if_zero { target.get(vm)? } else { next };
+        Ok(true)
+    })
+}
+
+fn translate(instruction: Instruction, next: i64) -> Op {
+    match instruction {
+        Instruction::Add { add1, add2, dest } => binary(add1, add2, dest, next, |vm, a, b| vm.add(a, b)),
+        Instruction::Mul { mul1, mul2, dest } => binary(mul1, mul2, dest, next, |vm, a, b| vm.mul(a, b)),
+        Instruction::WriteLess { test_a, test_b, dest } => binary(test_a, test_b, dest, next, |_, a, b| Ok((a < b) as i64)),
+        Instruction::WriteEqual { test_a, test_b, dest } => binary(test_a, test_b, dest, next, |_, a, b| Ok((a == b) as i64)),
+        Instruction::Jnz { test, abs_target } => branch(test, abs_target, next, false),
+        Instruction::Jz { test, abs_target } => branch(test, abs_target, next, true),
+        Instruction::In { dest } => {
+            let dest = Operand::new(dest);
+            Box::new(move |vm, io| {
+                let address = dest.address(vm)?;
+                let input = match io.input() {
+                    Some(input) => input,
+                    None => return Ok(false)
+                };
+                vm.write(address, input)?;
+                vm.ip = next;
+                Ok(true)
+            })
+        },
+        Instruction::Out { data } => {
+            let data = Operand::new(data);
+            Box::new(move |vm, io| {
+                io.output(data.get(vm)?);
+                vm.ip = next;
+                Ok(true)
+            })
+        },
+        Instruction::SetBase { base } => {
+            let base = Operand::new(base);
+            Box::new(move |vm, _| {
+                vm.base = vm.add(vm.base, base.get(vm)?)?;
+                vm.ip = next;
+                Ok(true)
+            })
+        },
+        Instruction::Halt => Box::new(|_, _| Ok(false))
+    }
+}
+
+pub fn compile(image: &[i64]) -> Compiled {
+    let starts = code_starts(image, &[0]);
+    let mut ops: Vec<Option<Op>> = (0..image.len()).map(|_| None).collect();
+    let mut covered = vec![false; image.len()];
+
+    for (address, start) in starts.iter().enumerate() {
+        if !start {
+            continue;
+        }
+        let instruction = decode_at(image, address as i64).expect("code start decodes");
+        let size = instruction.size() as usize;
+        for word in &mut covered[address..address + size] {
+            *word = true;
+        }
+        ops[address] = Some(translate(instruction, (address + size) as i64));
+    }
+
+    Compiled { image: image.to_vec(), ops, covered }
+}
+
+impl Compiled {
+    pub fn covers(&self, address: i64) -> bool {
+        address >= 0 && self.covered.get(address as usize).copied().unwrap_or(false)
+    }
+
+    // Whether memory still holds the code this was compiled from.
+    pub fn matches(&self, memory: &Memory) -> bool {
+        self.covered.iter().enumerate()
+            .all(|(address, covered)| !covered || memory.get(address as i64) == self.image[address])
+    }
+
+    pub fn instructions(&self) -> usize {
+        self.ops.iter().filter(|op| op.is_some()).count()
+    }
+}
+
+// Lets run_with hand any device, sized or not, to the compiled closures.
+struct Forward<'a, D: IoDevice + ?Sized>(&'a mut D);
+
+impl<'a, D: IoDevice + ?Sized> IoDevice for Forward<'a, D> {
+    fn input(&mut self) -> Option<i64> {
+        self.0.input()
+    }
+
+    fn output(&mut self, v: i64) {
+        self.0.output(v)
+    }
+}
+
+impl Vm {
+    // Compiles the code currently in memory and switches this machine over to it. The result
+    // can be shared with other machines running the same program through use_compiled.
+    pub fn compile(&mut self) -> Arc<Compiled> {
+        let end = self.memory.pages().last().map_or(0, |(start, words)| {
+            start as usize + words.iter().rposition(|w| *w != 0).map_or(0, |i| i + 1)
+        });
+        let compiled = Arc::new(compile(&self.memory.image(end)));
+        self.compiled = Some(compiled.clone());
+        compiled
+    }
+
+    // Returns false, and keeps interpreting, if memory doesn't hold the compiled program.
+    pub fn use_compiled(&mut self, compiled: Arc<Compiled>) -> bool {
+        let matches = compiled.matches(&self.memory);
+        self.compiled = if matches { Some(compiled) } else { None };
+        matches
+    }
+
+    pub fn is_compiled(&self) -> bool {
+        self.compiled.is_some()
+    }
+
+    pub(crate) fn run_compiled<D: IoDevice + ?Sized>(&mut self, compiled: Arc<Compiled>, io: &mut D) -> Result<(), VmError> {
+        let mut io = Forward(io);
+        loop {
+            // a write into the code region drops the compiled program; finish interpreting
+            if self.compiled.is_none() {
+                while self.step_with(&mut io)? {}
+                return Ok(());
+            }
+
+            let op = if self.ip >= 0 { compiled.ops.get(self.ip as usize) } else { None };
+            let running = match op {
+                Some(Some(op)) => {
+                    self.burn()?;
+                    op(self, &mut io)?
+                },
+                _ => self.step_with(&mut io)?
+            };
+            if !running {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn both(program: Vec<i64>, inputs: Vec<i64>) -> (Vm, Vm) {
+        let mut interpreted = Vm::with_inputs(program.clone(), inputs.clone());
+        interpreted.run().unwrap();
+
+        let mut compiled = Vm::with_inputs(program, inputs);
+        compiled.compile();
+        compiled.run().unwrap();
+
+        assert_eq!(compiled.outputs, interpreted.outputs);
+        assert_eq!(compiled.ip, interpreted.ip);
+        assert_eq!(compiled.memory, interpreted.memory);
+        (interpreted, compiled)
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        let quine = vec!(109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99);
+        let (_, vm) = both(quine.clone(), vec!());
+        assert_eq!(vm.outputs, quine);
+        assert!(vm.is_compiled());
+
+        // day 5's larger example: 999 below 8, 1000 at 8, 1001 above
+        let cmp8 = vec!(3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,
+                        999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99);
+        for (input, output) in [(7, 999), (8, 1000), (9, 1001)].iter() {
+            let (_, vm) = both(cmp8.clone(), vec!(*input));
+            assert_eq!(vm.outputs, vec!(*output));
+        }
+
+        let (_, vm) = both(vec!(1102,34915192,34915192,7,4,7,99,0), vec!());
+        assert_eq!(vm.outputs, vec!(1219070632396864));
+    }
+
+    #[test]
+    fn test_waits_for_input() {
+        let mut vm = Vm::with_inputs(vec!(3,9,4,9,3,9,4,9,99,0), vec!(5));
+        vm.compile();
+        vm.run().unwrap();
+        assert_eq!(vm.outputs, vec!(5));
+        assert!(vm.needs_input());
+
+        vm.push_input(6);
+        vm.run().unwrap();
+        assert_eq!(vm.outputs, vec!(5, 6));
+        assert!(vm.halted());
+    }
+
+    #[test]
+    fn test_self_modifying_falls_back() {
+        // rewrites the OUT's operand on the first pass, see the decode cache tests
+        let program = vec!(1101,7,8,30,4,29,1101,30,0,5,1001,31,1,31,1008,31,2,32,1006,32,0,99);
+        let (_, vm) = both(program, vec!());
+        assert_eq!(vm.outputs, vec!(0, 15));
+        assert!(!vm.is_compiled());
+    }
+
+    #[test]
+    fn test_errors_match() {
+        let mut vm = Vm::new(vec!(11101,1,1,0,99));
+        vm.compile();
+        assert_eq!(vm.run().unwrap_err(), Vm::new(vec!(11101,1,1,0,99)).run().unwrap_err());
+    }
+
+    #[test]
+    fn test_sharing() {
+        let program = vec!(3,9,1002,9,3,9,4,9,99,0);
+        let compiled = Vm::new(program.clone()).compile();
+        assert_eq!(compiled.instructions(), 4);
+
+        for input in 1..4 {
+            let mut vm = Vm::with_inputs(program.clone(), vec!(input));
+            assert!(vm.use_compiled(compiled.clone()));
+            vm.run().unwrap();
+            assert_eq!(vm.outputs, vec!(input * 3));
+        }
+
+        let mut other = Vm::new(vec!(4,9,99));
+        assert!(!other.use_compiled(compiled));
+    }
+}
@@ -0,0 +1,391 @@
+use std::convert::TryFrom;
+use std::sync::mpsc::channel;
+
+use crate::bignum::BigVm;
+use crate::cache::DecodeCache;
+use crate::network::{Network, NetworkError, Route};
+use crate::threaded::{self, ChannelIo};
+use crate::{Arithmetic, Mode, Vm, VmError, OP_ADD, OP_BASE, OP_IN, OP_JNZ, OP_JZ, OP_MUL, OP_OUT, OP_WEQ, OP_WLT};
+
+// Every way we have of running a machine. They should all be indistinguishable from outside.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Backend {
+    Interpreter,
+    Cached,
+    Compiled,
+    // A single node in a cooperative network, collecting its own outputs.
+    Network,
+    // On its own thread with channels for input and output.
+    Threaded,
+    // The arbitrary-precision machine that Arithmetic::Promote hands over to. It never
+    // overflows, so it sits out the cases where the others do.
+    Big
+}
+
+pub const BACKENDS: [Backend; 6] = [Backend::Interpreter, Backend::Cached, Backend::Compiled, Backend::Network, Backend::Threaded, Backend::Big];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub name: String,
+    pub program: Vec<i64>,
+    pub inputs: Vec<i64>,
+    // None when the program must fail; the error then has to match the interpreter's.
+    pub outputs: Option<Vec<i64>>,
+    // (address, value) pairs that must hold once the program stops.
+    pub memory: Vec<(i64, i64)>
+}
+
+impl Case {
+    fn new(name: &str, program: &[i64], inputs: &[i64], outputs: &[i64]) -> Case {
+        Case { name: name.to_string(), program: program.to_vec(), inputs: inputs.to_vec(), outputs: Some(outputs.to_vec()), memory: vec!() }
+    }
+
+    fn failing(name: &str, program: &[i64]) -> Case {
+        Case { name: name.to_string(), program: program.to_vec(), inputs: vec!(), outputs: None, memory: vec!() }
+    }
+
+    fn with_memory(mut self, memory: &[(i64, i64)]) -> Case {
+        self.memory = memory.to_vec();
+        self
+    }
+}
+
+// The machine every backend starts from. It traps on overflow, so that overflowing is an error
+// the backends have to agree on rather than a value.
+pub fn machine(program: &[i64], inputs: &[i64]) -> Vm {
+    let mut vm = Vm::with_inputs(program.to_vec(), inputs.to_vec());
+    vm.arithmetic = Arithmetic::Trap;
+    vm
+}
+
+// Runs the program to completion (or until it runs out of input) and hands back the machine,
+// with everything it output in `outputs` whichever backend ran it.
+pub fn run(backend: Backend, program: &[i64], inputs: &[i64]) -> Result<Vm, VmError> {
+    run_on(backend, machine(program, inputs))
+}
+
+// Like run, but on a machine that's already set up, e.g. with a ration of fuel.
+pub fn run_on(backend: Backend, mut vm: Vm) -> Result<Vm, VmError> {
+    match backend {
+        Backend::Interpreter => vm.run()?,
+        Backend::Cached => {
+            vm.cache = Some(DecodeCache::new());
+            vm.run()?
+        },
+        Backend::Compiled => {
+            vm.compile();
+            vm.run()?
+        },
+        Backend::Network => {
+            let mut network = Network::chain(vec!(vm));
+            network.run().map_err(|e| match e {
+                NetworkError::Machine { error, .. } => error,
+                e => panic!("{}", e)
+            })?;
+            let node = network.nodes.pop().expect("one node");
+            assert_eq!(node.route, Route::Collect);
+            vm = node.vm;
+            vm.outputs = node.collected;
+        },
+        Backend::Threaded => {
+            let (sender, receiver) = channel();
+            let (output, outputs) = channel();
+            drop(sender);
+            let finished = threaded::spawn(vm, ChannelIo::new(receiver, output, None)).join().expect("vm thread panicked")?;
+            vm = finished.vm;
+            vm.outputs = outputs.try_iter().collect();
+        },
+        Backend::Big => {
+            let mut big = BigVm::from(&vm);
+            big.run()?;
+            vm = Vm::try_from(&big)?;
+        }
+    }
+    Ok(vm)
+}
+
+// Describes how the backend disagrees with the case, if it does.
+pub fn check(backend: Backend, case: &Case) -> Result<(), String> {
+    if backend == Backend::Big && overflows(case) {
+        return Ok(());
+    }
+    let result = run(backend, &case.program, &case.inputs);
+    let vm = match (&case.outputs, result) {
+        (Some(_), Err(e)) => return Err(format!("{} on {:?}: unexpected error: {}", case.name, backend, e)),
+        (None, Ok(vm)) => return Err(format!("{} on {:?}: expected an error, got {:?}", case.name, backend, vm.outputs)),
+        (None, Err(e)) => {
+            let expected = run(Backend::Interpreter, &case.program, &case.inputs).err();
+            return if expected.as_ref() == Some(&e) {
+                Ok(())
+            } else {
+                Err(format!("{} on {:?}: error {:?}, interpreter gave {:?}", case.name, backend, e, expected))
+            };
+        },
+        (Some(_), Ok(vm)) => vm
+    };
+
+    if case.outputs.as_ref() != Some(&vm.outputs) {
+        return Err(format!("{} on {:?}: output {:?}, expected {:?}", case.name, backend, vm.outputs, case.outputs.as_ref().unwrap()));
+    }
+    for (address, value) in &case.memory {
+        if vm.peek(*address) != *value {
+            return Err(format!("{} on {:?}: [{}] = {}, expected {}", case.name, backend, address, vm.peek(*address), value));
+        }
+    }
+    Ok(())
+}
+
+// Whether the interpreter stops on an overflow, where the big-integer machine would carry on.
+fn overflows(case: &Case) -> bool {
+    matches!(run(Backend::Interpreter, &case.program, &case.inputs), Err(VmError::Overflow { .. }))
+}
+
+// Every backend against every case; the failures, if any.
+pub fn check_all(cases: &[Case]) -> Vec<String> {
+    BACKENDS.iter()
+        .flat_map(|backend| cases.iter().map(move |case| check(*backend, case)))
+        .filter_map(Result::err)
+        .collect()
+}
+
+// The mode-combination programs all run with the relative base at 100 and keep their data at
+// fixed addresses, so the same value is reached whichever mode an operand uses.
+const BASE: i64 = 100;
+const X: i64 = 7;
+const Y: i64 = -3;
+const X_AT: i64 = 50;
+const Y_AT: i64 = 51;
+const DEST_AT: i64 = 52;
+const TARGET_AT: i64 = 53;
+const FAR_AT: i64 = 54;
+const FAR: i64 = 1234;
+
+type Binary = fn(i64, i64) -> i64;
+type Expected = fn(i64) -> bool;
+
+const READ_MODES: [Mode; 3] = [Mode::Normal, Mode::Imm, Mode::Base];
+const WRITE_MODES: [Mode; 2] = [Mode::Normal, Mode::Base];
+
+fn digit(mode: Mode) -> i64 {
+    match mode {
+        Mode::Normal => 0,
+        Mode::Imm => 1,
+        Mode::Base => 2
+    }
+}
+
+fn mode_name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Normal => "pos",
+        Mode::Imm => "imm",
+        Mode::Base => "rel"
+    }
+}
+
+// The operand that reaches `address` (or is `value`, in immediate mode).
+fn operand(mode: Mode, address: i64, value: i64) -> i64 {
+    match mode {
+        Mode::Normal => address,
+        Mode::Imm => value,
+        Mode::Base => address - BASE
+    }
+}
+
+fn word(op: i64, modes: &[Mode]) -> i64 {
+    modes.iter().rev().fold(0, |word, mode| word * 10 + digit(*mode)) * 100 + op
+}
+
+// Sets the base, runs `code`, then lays out the data the operands point at.
+fn framed(code: &[i64]) -> Vec<i64> {
+    let mut program = vec!(109, BASE);
+    program.extend(code);
+    assert!(program.len() <= X_AT as usize);
+    program.resize(X_AT as usize, 0);
+    program.extend(&[X, Y, 0, 0, FAR]);
+    program
+}
+
+fn mode_cases() -> Vec<Case> {
+    let mut cases = vec!();
+    let binary: [(i64, &str, Binary); 4] = [
+        (OP_ADD, "add", |a, b| a + b),
+        (OP_MUL, "mul", |a, b| a * b),
+        (OP_WLT, "lt", |a, b| (a < b) as i64),
+        (OP_WEQ, "eq", |a, b| (a == b) as i64)
+    ];
+
+    for (op, name, f) in binary.iter() {
+        for a in READ_MODES.iter() {
+            for b in READ_MODES.iter() {
+                for dest in WRITE_MODES.iter() {
+                    let program = framed(&[
+                        word(*op, &[*a, *b, *dest]), operand(*a, X_AT, X), operand(*b, Y_AT, Y), operand(*dest, DEST_AT, 0),
+                        4, DEST_AT,
+                        99
+                    ]);
+                    let name = format!("{} {} {} {}", name, mode_name(*a), mode_name(*b), mode_name(*dest));
+                    cases.push(Case::new(&name, &program, &[], &[f(X, Y)]).with_memory(&[(DEST_AT, f(X, Y))]));
+                }
+            }
+        }
+    }
+
+    // the jump lands on the OUT #1; falling through reaches the OUT #0
+    for (op, name, jumps) in [(OP_JNZ, "jnz", true), (OP_JZ, "jz", false)].iter() {
+        for test in READ_MODES.iter() {
+            for target in READ_MODES.iter() {
+                let landing = 8;
+                let mut program = framed(&[
+                    word(*op, &[*test, *target]), operand(*test, X_AT, X), operand(*target, TARGET_AT, landing),
+                    104, 0,
+                    99,
+                    104, 1,
+                    99
+                ]);
+                program[TARGET_AT as usize] = landing;
+                let name = format!("{} {} {}", name, mode_name(*test), mode_name(*target));
+                cases.push(Case::new(&name, &program, &[], &[*jumps as i64]));
+            }
+        }
+    }
+
+    for dest in WRITE_MODES.iter() {
+        let program = framed(&[word(OP_IN, &[*dest]), operand(*dest, DEST_AT, 0), 4, DEST_AT, 99]);
+        cases.push(Case::new(&format!("in {}", mode_name(*dest)), &program, &[42], &[42]).with_memory(&[(DEST_AT, 42)]));
+    }
+
+    for data in READ_MODES.iter() {
+        let program = framed(&[word(OP_OUT, &[*data]), operand(*data, X_AT, X), 99]);
+        cases.push(Case::new(&format!("out {}", mode_name(*data)), &program, &[], &[X]));
+    }
+
+    // moves the base up by X, then reads FAR through the new base
+    for offset in READ_MODES.iter() {
+        let program = framed(&[word(OP_BASE, &[*offset]), operand(*offset, X_AT, X), 204, FAR_AT - BASE - X, 99]);
+        cases.push(Case::new(&format!("arb {}", mode_name(*offset)), &program, &[], &[FAR]));
+    }
+
+    cases
+}
+
+// Programs published with the puzzles, plus a few edge cases.
+fn golden_cases() -> Vec<Case> {
+    let quine = [109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
+    let compare8 = [3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,
+                    999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99];
+
+    let mut cases = vec!(
+        Case::new("day 2 example", &[1,9,10,3,2,3,11,0,99,30,40,50], &[], &[]).with_memory(&[(0, 3500), (3, 70)]),
+        Case::new("day 2 multiply in place", &[2,4,4,5,99,0], &[], &[]).with_memory(&[(5, 9801)]),
+        Case::new("day 2 self-modifying", &[1,1,1,4,99,5,6,0,99], &[], &[]).with_memory(&[(0, 30), (4, 2)]),
+        Case::new("day 5 echo", &[3,0,4,0,99], &[-17], &[-17]),
+        Case::new("day 5 modes", &[1002,4,3,4,33], &[], &[]).with_memory(&[(4, 99)]),
+        Case::new("day 5 negative", &[1101,100,-1,4,0], &[], &[]).with_memory(&[(4, 99)]),
+        Case::new("day 9 quine", &quine, &[], &quine),
+        Case::new("day 9 sixteen digits", &[1102,34915192,34915192,7,4,7,99,0], &[], &[1219070632396864]),
+        Case::new("day 9 large literal", &[104,1125899906842624,99], &[], &[1125899906842624]),
+        Case::new("large subtraction", &[1101,-4611686018427387904,-4611686018427387904,7,4,7,99,0], &[], &[i64::MIN]),
+        Case::new("relative write", &[109,5,203,0,99,0], &[1], &[]).with_memory(&[(5, 1)]),
+        Case::new("relative read and write", &[109,20,21101,3,4,-3,204,-3,99], &[], &[7]).with_memory(&[(17, 7)]),
+        Case::new("negative base", &[109,-5,21201,10,0,15,204,15,99,0,0], &[], &[15]).with_memory(&[(10, 15)]),
+        Case::new("far memory", &[1101,3,4,100000,4,100000,99], &[], &[7]).with_memory(&[(100000, 7)]),
+        Case::new("read beyond the memory limit", &[4,1099511627776,99], &[], &[0]),
+        Case::new("read the last address", &[4,i64::MAX,99], &[], &[0]),
+        Case::new("relative read beyond the memory limit", &[109,i64::MAX - 1,204,1,99], &[], &[0]),
+        Case::failing("write beyond the memory limit", &[1101,1,1,1099511627776,99]),
+        Case::failing("jump beyond the memory limit", &[1105,1,1099511627776]),
+        Case::new("waits for input", &[3,9,4,9,3,9,4,9,99,0], &[5], &[5]).with_memory(&[(9, 5)]),
+        Case::failing("unknown op", &[42]),
+        Case::failing("unknown mode", &[30001,0,0,0,99]),
+        Case::failing("immediate write", &[11101,1,1,0,99]),
+        Case::failing("negative address", &[1,-1,0,0,99]),
+        Case::failing("negative write", &[1101,1,1,-1,99]),
+        Case::failing("runs off the end", &[1101,1,1,5,4,5]),
+        Case::failing("add overflow", &[1101,i64::MAX,1,0,99]),
+        Case::failing("mul overflow", &[21102,-4611686018427387905,2,0,99]),
+        Case::failing("relative overflow", &[109,9223372036854775807,204,1,99]),
+        Case::failing("base overflow", &[109,9223372036854775807,109,1,99]),
+        Case::failing("immediate input", &[103,0,99]),
+        Case::new("waits to read into a bad address", &[3,-1,99], &[], &[])
+    );
+
+    // day 5's comparison programs: equal to / less than 8, in position and immediate mode
+    let comparisons: [(&str, [i64; 11], Expected); 4] = [
+        ("day 5 pos eq 8", [3,9,8,9,10,9,4,9,99,-1,8], |i| i == 8),
+        ("day 5 pos lt 8", [3,9,7,9,10,9,4,9,99,-1,8], |i| i < 8),
+        ("day 5 imm eq 8", [3,3,1108,-1,8,3,4,3,99,0,0], |i| i == 8),
+        ("day 5 imm lt 8", [3,3,1107,-1,8,3,4,3,99,0,0], |i| i < 8)
+    ];
+    for (name, program, expected) in comparisons.iter() {
+        for input in [7, 8, 9].iter() {
+            cases.push(Case::new(&format!("{} with {}", name, input), program, &[*input], &[expected(*input) as i64]));
+        }
+    }
+
+    let jumps: [(&str, &[i64]); 2] = [
+        ("day 5 pos jump", &[3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9]),
+        ("day 5 imm jump", &[3,3,1105,-1,9,1101,0,0,12,4,12,99,1])
+    ];
+    for (name, program) in jumps.iter() {
+        for input in [0, 5].iter() {
+            cases.push(Case::new(&format!("{} with {}", name, input), program, &[*input], &[(*input != 0) as i64]));
+        }
+    }
+
+    for (input, output) in [(7, 999), (8, 1000), (9, 1001)].iter() {
+        cases.push(Case::new(&format!("day 5 compare with {}", input), &compare8, &[*input], &[*output]));
+    }
+
+    cases
+}
+
+pub fn cases() -> Vec<Case> {
+    let mut cases = mode_cases();
+    cases.extend(golden_cases());
+    cases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_backend() {
+        let failures = check_all(&cases());
+        assert!(failures.is_empty(), "{} failures:\n{}", failures.len(), failures.join("\n"));
+    }
+
+    #[test]
+    fn test_covers_every_mode() {
+        // 4 binary ops * 3 * 3 * 2, 2 jumps * 3 * 3, IN * 2, OUT * 3, ARB * 3
+        assert_eq!(mode_cases().len(), 72 + 18 + 2 + 3 + 3);
+        let mut names: Vec<String> = cases().into_iter().map(|case| case.name).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), cases().len());
+    }
+
+    #[test]
+    fn test_fuel_runs_out_on_every_backend() {
+        for backend in BACKENDS.iter() {
+            let mut vm = machine(&[104,1,99], &[]);
+            vm.fuel = Some(2);
+            assert_eq!(run_on(*backend, vm).unwrap().outputs, vec!(1), "{:?}", backend);
+
+            // jumps back to itself forever
+            let mut vm = machine(&[1105,1,0], &[]);
+            vm.fuel = Some(100);
+            assert!(matches!(run_on(*backend, vm), Err(VmError::OutOfFuel { fault }) if fault.ip == 0), "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn test_check_reports_mismatches() {
+        let wrong = Case::new("wrong", &[104,1,99], &[], &[2]);
+        assert_eq!(check(Backend::Compiled, &wrong), Err("wrong on Compiled: output [1], expected [2]".to_string()));
+        let passes = Case::new("passes", &[104,1,99], &[], &[1]);
+        assert!(check(Backend::Compiled, &passes.clone().with_memory(&[(1, 1)])).is_ok());
+        assert!(check(Backend::Threaded, &passes.with_memory(&[(1, 2)])).is_err());
+        assert!(check(Backend::Network, &Case::failing("halts", &[99])).is_err());
+    }
+}
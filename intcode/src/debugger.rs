@@ -0,0 +1,363 @@
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::snapshot::Snapshot;
+use crate::{Vm, VmError};
+
+const WORDS_PER_ROW: i64 = 8;
+const DEFAULT_DUMP: i64 = 16;
+// Enough to look through a whole program without a typo flooding the terminal.
+const MAX_DUMP: i64 = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    Stepped,
+    Breakpoint(i64),
+    Watchpoint { address: i64, old: i64, new: i64 },
+    NeedsInput,
+    Halted,
+    Error(VmError)
+}
+
+pub struct Debugger {
+    pub vm: Vm,
+    pub breakpoints: BTreeSet<i64>,
+    pub watchpoints: BTreeSet<i64>,
+    shown_outputs: usize
+}
+
+impl Debugger {
+    pub fn new(vm: Vm) -> Debugger {
+        Debugger {
+            vm,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            shown_outputs: 0
+        }
+    }
+
+    // Executes a single instruction, reporting any watched address it changed.
+    pub fn step(&mut self) -> Stop {
+        if self.vm.halted() {
+            return Stop::Halted;
+        }
+
+        let before: Vec<(i64, i64)> = self.watchpoints.iter()
+            .map(|address| (*address, self.vm.peek(*address)))
+            .collect();
+
+        match self.vm.step() {
+            Err(e) => return Stop::Error(e),
+            Ok(false) if self.vm.halted() => return Stop::Halted,
+            Ok(false) => return Stop::NeedsInput,
+            Ok(true) => {}
+        }
+
+        for (address, old) in before {
+            let new = self.vm.peek(address);
+            if new != old {
+                return Stop::Watchpoint { address, old, new };
+            }
+        }
+
+        Stop::Stepped
+    }
+
+    // Runs until something interesting happens. A breakpoint on the starting ip is ignored
+    // so that continuing from a breakpoint makes progress.
+    pub fn resume(&mut self) -> Stop {
+        let mut first = true;
+        loop {
+            if !first && self.breakpoints.contains(&self.vm.ip) {
+                return Stop::Breakpoint(self.vm.ip);
+            }
+            first = false;
+
+            match self.step() {
+                Stop::Stepped => {},
+                stop => return stop
+            }
+        }
+    }
+
+    pub fn describe_next(&self) -> String {
+        match self.vm.next_instruction() {
+            Ok(instruction) => format!("{:>5}: {}", self.vm.ip, instruction),
+            Err(e) => format!("{:>5}: <{}>", self.vm.ip, e)
+        }
+    }
+
+    // Shows at most MAX_DUMP words, stopping short at the top of the address space.
+    pub fn dump(&self, start: i64, count: i64) -> String {
+        let mut result = String::new();
+        let last = start.saturating_add(count.min(MAX_DUMP));
+        let mut address = start;
+        while address < last {
+            let end = address.saturating_add(WORDS_PER_ROW).min(last);
+            let words: Vec<String> = (address..end).map(|a| self.vm.peek(a).to_string()).collect();
+            writeln!(result, "{:>5}: {}", address, words.join(" ")).unwrap();
+            address = end;
+        }
+        result
+    }
+
+    fn new_outputs(&mut self) -> Option<String> {
+        if self.vm.outputs.len() <= self.shown_outputs {
+            return None;
+        }
+        let outputs = &self.vm.outputs[self.shown_outputs..];
+        self.shown_outputs = self.vm.outputs.len();
+        Some(format!("output: {:?}", outputs))
+    }
+
+    fn report(&mut self, stop: Stop) -> String {
+        let mut result = String::new();
+        if let Some(outputs) = self.new_outputs() {
+            writeln!(result, "{}", outputs).unwrap();
+        }
+        match stop {
+            Stop::Stepped => {},
+            Stop::Breakpoint(ip) => writeln!(result, "breakpoint at {}", ip).unwrap(),
+            Stop::Watchpoint { address, old, new } =>
+                writeln!(result, "watchpoint: [{}] {} -> {}", address, old, new).unwrap(),
+            Stop::NeedsInput => writeln!(result, "waiting for input").unwrap(),
+            Stop::Halted => writeln!(result, "halted").unwrap(),
+            Stop::Error(e) => writeln!(result, "error: {}", e).unwrap()
+        }
+        writeln!(result, "{}", self.describe_next()).unwrap();
+        result
+    }
+
+    // Runs one line of debugger commands and returns what to print, or None to quit.
+    pub fn command(&mut self, line: &str) -> Option<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match (words.first().copied(), words.get(1)) {
+            (Some("save"), Some(path)) => return Some(match self.vm.snapshot().save(Path::new(path)) {
+                Ok(()) => format!("saved to {}\n", path),
+                Err(e) => format!("failed to save {}: {}\n", path, e)
+            }),
+            (Some("load"), Some(path)) => return Some(match Snapshot::load(Path::new(path)) {
+                Ok(snapshot) => {
+                    self.vm.restore(&snapshot);
+                    self.shown_outputs = self.vm.outputs.len();
+                    format!("loaded {}\n{}\n", path, self.describe_next())
+                },
+                Err(e) => format!("failed to load {}: {}\n", path, e)
+            }),
+            _ => {}
+        }
+
+        let numbers: Result<Vec<i64>, _> = words.iter().skip(1).map(|w| w.parse::<i64>()).collect();
+        let numbers = match numbers {
+            Ok(numbers) => numbers,
+            Err(_) => return Some(format!("expected numbers after {}\n", words[0]))
+        };
+        let arg = |i: usize| numbers.get(i).copied();
+
+        let result = match words.first().copied().unwrap_or("") {
+            "s" | "step" => {
+                let mut stop = Stop::Stepped;
+                for _ in 0..arg(0).unwrap_or(1) {
+                    stop = self.step();
+                    if stop != Stop::Stepped {
+                        break;
+                    }
+                }
+                self.report(stop)
+            },
+            "c" | "continue" => {
+                let stop = self.resume();
+                self.report(stop)
+            },
+            "b" | "break" => match arg(0) {
+                Some(address) => {
+                    self.breakpoints.insert(address);
+                    format!("breakpoint at {}\n", address)
+                },
+                None => format!("breakpoints: {:?}\n", self.breakpoints)
+            },
+            "d" | "delete" => match arg(0) {
+                Some(address) => {
+                    self.breakpoints.remove(&address);
+                    format!("deleted breakpoint at {}\n", address)
+                },
+                None => {
+                    self.breakpoints.clear();
+                    "deleted all breakpoints\n".to_string()
+                }
+            },
+            "w" | "watch" => match arg(0) {
+                Some(address) => {
+                    self.watchpoints.insert(address);
+                    format!("watching [{}] = {}\n", address, self.vm.peek(address))
+                },
+                None => format!("watchpoints: {:?}\n", self.watchpoints)
+            },
+            "uw" | "unwatch" => match arg(0) {
+                Some(address) => {
+                    self.watchpoints.remove(&address);
+                    format!("stopped watching [{}]\n", address)
+                },
+                None => {
+                    self.watchpoints.clear();
+                    "stopped watching everything\n".to_string()
+                }
+            },
+            "x" | "dump" => {
+                let start = arg(0).unwrap_or(self.vm.ip);
+                let count = arg(1).unwrap_or(DEFAULT_DUMP);
+                let dump = self.dump(start, count);
+                if count > MAX_DUMP {
+                    format!("{}(only the first {} words)\n", dump, MAX_DUMP)
+                } else {
+                    dump
+                }
+            },
+            "n" | "next" => format!("{}\n", self.describe_next()),
+            "r" | "regs" => format!("ip {} base {} pending input {:?}\n", self.vm.ip, self.vm.base, self.vm.inputs),
+            "i" | "input" => {
+                self.vm.inputs.extend(numbers.iter());
+                format!("pending input {:?}\n", self.vm.inputs)
+            },
+            "o" | "output" => format!("output: {:?}\n", self.vm.outputs),
+            "debug" => match u8::try_from(arg(0).unwrap_or(0)) {
+                Ok(level) => {
+                    self.vm.debug = level;
+                    format!("debug level {}\n", level)
+                },
+                Err(_) => format!("debug levels run from 0 to {}\n", u8::MAX)
+            },
+            "q" | "quit" => return None,
+            "h" | "help" | "" => HELP.to_string(),
+            other => format!("unknown command {}, try help\n", other)
+        };
+
+        Some(result)
+    }
+}
+
+const HELP: &str = "\
+s|step [n]         execute n instructions (default 1)
+c|continue         run to a breakpoint, watchpoint, halt or input wait
+b|break [addr]     set a breakpoint on an ip, or list breakpoints
+d|delete [addr]    remove a breakpoint, or all of them
+w|watch [addr]     stop when a memory address changes, or list watchpoints
+uw|unwatch [addr]  remove a watchpoint, or all of them
+x|dump [addr] [n]  show n words of memory from addr (default ip, 16)
+n|next             show the next instruction
+r|regs             show ip, relative base and pending input
+i|input v...       queue input values
+o|output           show everything the program has output
+debug [level]      set the VM's debug print level
+save|load file     write the machine state to a snapshot file, or read it back
+q|quit             exit
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debugger(program: Vec<i64>) -> Debugger {
+        Debugger::new(Vm::new(program))
+    }
+
+    #[test]
+    fn test_step_and_next() {
+        let mut db = debugger(vec!(1101,1,2,5,99,0));
+        assert_eq!(db.command("n").unwrap(), "    0: ADD  #1, #2, [5]\n");
+        assert_eq!(db.command("s").unwrap(), "    4: HLT\n");
+        assert_eq!(db.command("s").unwrap(), "halted\n    4: HLT\n");
+        assert_eq!(db.vm.peek(5), 3);
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        // count [20] up to 3, outputting each value
+        let program = vec!(1001,20,1,20,4,20,1008,20,3,21,1006,21,0,99);
+        let mut db = debugger(program);
+        db.command("b 4");
+        assert_eq!(db.resume(), Stop::Breakpoint(4));
+        assert_eq!(db.vm.peek(20), 1);
+        assert_eq!(db.resume(), Stop::Breakpoint(4));
+        assert_eq!(db.vm.peek(20), 2);
+        db.command("d");
+        assert_eq!(db.resume(), Stop::Halted);
+        assert_eq!(db.vm.outputs, vec!(1, 2, 3));
+    }
+
+    #[test]
+    fn test_watchpoint_past_program() {
+        let program = vec!(1101,1,2,100,1101,3,4,200,1101,5,6,100,99);
+        let mut db = debugger(program);
+        db.command("w 100");
+        assert_eq!(db.resume(), Stop::Watchpoint { address: 100, old: 0, new: 3 });
+        assert_eq!(db.resume(), Stop::Watchpoint { address: 100, old: 3, new: 11 });
+        assert_eq!(db.resume(), Stop::Halted);
+    }
+
+    #[test]
+    fn test_input_and_output() {
+        let mut db = debugger(vec!(3,7,4,7,99,0,0,0));
+        assert_eq!(db.command("c").unwrap(), "waiting for input\n    0: IN   [7]\n");
+        db.command("i 42");
+        assert_eq!(db.command("c").unwrap(), "output: [42]\nhalted\n    4: HLT\n");
+    }
+
+    #[test]
+    fn test_dump_and_regs() {
+        let mut db = debugger(vec!(109,-2,99));
+        db.command("s");
+        assert_eq!(db.command("r").unwrap(), "ip 2 base -2 pending input []\n");
+        assert_eq!(db.command("x 0 3").unwrap(), "    0: 109 -2 99\n");
+        assert_eq!(db.dump(0, 10), "    0: 109 -2 99 0 0 0 0 0\n    8: 0 0\n");
+    }
+
+    #[test]
+    fn test_dump_limits() {
+        let mut db = debugger(vec!(99));
+        assert_eq!(db.command("x 9223372036854775800 100").unwrap(), "9223372036854775800: 0 0 0 0 0 0 0\n");
+        let dump = db.command(&format!("x 0 {}", i64::MAX)).unwrap();
+        assert_eq!(dump.lines().count(), (MAX_DUMP / WORDS_PER_ROW) as usize + 1);
+        assert!(dump.ends_with("(only the first 4096 words)\n"));
+        assert_eq!(db.dump(0, -5), "");
+    }
+
+    #[test]
+    fn test_error_stops() {
+        let mut db = debugger(vec!(42));
+        assert!(db.command("c").unwrap().starts_with("error: no instruction for op 42 at ip 0"));
+        assert!(db.command("bogus").unwrap().starts_with("unknown command"));
+        assert!(db.command("quit").is_none());
+    }
+
+    #[test]
+    fn test_debug_level() {
+        let mut db = debugger(vec!(99));
+        assert_eq!(db.command("debug 2").unwrap(), "debug level 2\n");
+        assert_eq!(db.command("debug 256").unwrap(), "debug levels run from 0 to 255\n");
+        assert_eq!(db.command("debug -1").unwrap(), "debug levels run from 0 to 255\n");
+        assert_eq!(db.vm.debug, 2);
+        assert_eq!(db.command("debug").unwrap(), "debug level 0\n");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("intcode-debugger-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut db = debugger(vec!(3,7,4,7,99,0,0,0));
+        db.command("i 5");
+        db.command("s");
+        db.command(&format!("save {}", path));
+
+        db.command("c");
+        assert_eq!(db.vm.outputs, vec!(5));
+        assert_eq!(db.command(&format!("load {}", path)).unwrap(), format!("loaded {}\n    2: OUT  [7]\n", path));
+        assert!(db.vm.outputs.is_empty());
+        assert_eq!(db.vm.peek(7), 5);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(db.command("load /nonexistent/snapshot").unwrap().starts_with("failed to load"));
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+
+use crate::{decode, Arg, Instruction, Mode};
+
+const DATA_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Code { address: i64, instruction: Instruction },
+    Data { address: i64, words: Vec<i64> }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Code { address, instruction } => write!(f, "{:>5}: {}", address, instruction),
+            Line::Data { address, words } => {
+                let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+                write!(f, "{:>5}: DB   {}", address, words.join(", "))
+            }
+        }
+    }
+}
+
+pub fn decode_at(program: &[i64], address: i64) -> Option<Instruction> {
+    let memory = |a: i64| if a < 0 { 0 } else { program.get(a as usize).copied().unwrap_or(0) };
+    decode(memory, address).ok()
+}
+
+// Where control can go after the instruction at address. Jumps through memory can't be
+// followed statically, so only their fall through is returned.
+pub fn successors(address: i64, instruction: &Instruction) -> Vec<i64> {
+    let next = address + instruction.size();
+    let branch = |jump_if_zero: bool, test: &Arg, target: &Arg| {
+        let mut result = vec!();
+        let taken = match test.mode {
+            Mode::Imm => Some((test.value == 0) == jump_if_zero),
+            _ => None
+        };
+        if taken != Some(false) && target.mode == Mode::Imm {
+            result.push(target.value);
+        }
+        if taken != Some(true) {
+            result.push(next);
+        }
+        result
+    };
+
+    match instruction {
+        Instruction::Halt => vec!(),
+        Instruction::Jnz { test, abs_target } => branch(false, test, abs_target),
+        Instruction::Jz { test, abs_target } => branch(true, test, abs_target),
+        _ => vec!(next)
+    }
+}
+
+// Constants the instruction copies into memory. Puzzle programs push return addresses this
+// way before calling a function (e.g. `ADD #123, #0, [rb+1]`), so they are likely code.
+pub fn code_pointers(instruction: &Instruction) -> Vec<i64> {
+    let copied = |a: &Arg, b: &Arg, identity: i64| {
+        if a.mode == Mode::Imm && b.mode == Mode::Imm {
+            if b.value == identity {
+                return vec!(a.value);
+            } else if a.value == identity {
+                return vec!(b.value);
+            }
+        }
+        vec!()
+    };
+
+    match instruction {
+        Instruction::Add { add1, add2, .. } => copied(add1, add2, 0),
+        Instruction::Mul { mul1, mul2, .. } => copied(mul1, mul2, 1),
+        _ => vec!()
+    }
+}
+
+// Marks every address where a reachable instruction starts, walking from the given roots.
+pub fn code_starts(program: &[i64], roots: &[i64]) -> Vec<bool> {
+    let mut starts = vec![false; program.len()];
+    let mut pending: Vec<i64> = roots.to_vec();
+
+    while let Some(address) = pending.pop() {
+        if address < 0 || address >= program.len() as i64 || starts[address as usize] {
+            continue;
+        }
+        let instruction = match decode_at(program, address) {
+            Some(instruction) => instruction,
+            None => continue
+        };
+        if address + instruction.size() > program.len() as i64 {
+            continue;
+        }
+
+        starts[address as usize] = true;
+        pending.extend(successors(address, &instruction));
+        pending.extend(code_pointers(&instruction));
+    }
+
+    starts
+}
+
+pub fn disassemble(program: &[i64], extra_roots: &[i64]) -> Vec<Line> {
+    let mut roots = vec!(0);
+    roots.extend_from_slice(extra_roots);
+    let starts = code_starts(program, &roots);
+
+    let mut lines = vec!();
+    let mut address = 0;
+    while address < program.len() {
+        if starts[address] {
+            let instruction = decode_at(program, address as i64).expect("code start decodes");
+            let size = instruction.size() as usize;
+            lines.push(Line::Code { address: address as i64, instruction });
+            address += size;
+        } else {
+            let mut end = address;
+            while end < program.len() && !starts[end] && end - address < DATA_PER_LINE {
+                end += 1;
+            }
+            lines.push(Line::Data { address: address as i64, words: program[address..end].to_vec() });
+            address = end;
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(program: &[i64]) -> Vec<String> {
+        disassemble(program, &[]).iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_operands() {
+        assert_eq!(listing(&[1001,4,-2,5,204,-3,99]), vec!(
+            "    0: ADD  [4], #-2, [5]",
+            "    4: OUT  [rb-3]",
+            "    6: HLT"
+        ));
+        assert_eq!(listing(&[21107,1,2,7,99]), vec!(
+            "    0: LT   #1, #2, [rb+7]",
+            "    4: HLT"
+        ));
+    }
+
+    #[test]
+    fn test_data_after_halt() {
+        assert_eq!(listing(&[3,9,8,9,10,9,4,9,99,-1,8]), vec!(
+            "    0: IN   [9]",
+            "    2: EQ   [9], [10], [9]",
+            "    6: OUT  [9]",
+            "    8: HLT",
+            "    9: DB   -1, 8"
+        ));
+    }
+
+    #[test]
+    fn test_unconditional_jump_skips_data() {
+        let program = [1105,1,7,1,2,3,4,109,1,99];
+        assert_eq!(listing(&program), vec!(
+            "    0: JNZ  #1, #7",
+            "    3: DB   1, 2, 3, 4",
+            "    7: ARB  #1",
+            "    9: HLT"
+        ));
+    }
+
+    #[test]
+    fn test_return_address_is_code() {
+        // call the function at 9 with return address 7, which halts
+        let program = [21101,7,0,1,1105,1,9,99,0,109,1,2105,1,0];
+        let starts = code_starts(&program, &[0]);
+        assert!(starts[7]);
+        assert!(!starts[8]);
+        assert!(starts[9]);
+        assert!(starts[11]);
+    }
+
+    #[test]
+    fn test_long_data_is_split() {
+        let mut program = vec!(99);
+        program.extend(1..=10);
+        let lines = listing(&program);
+        assert_eq!(lines[1], "    1: DB   1, 2, 3, 4, 5, 6, 7, 8");
+        assert_eq!(lines[2], "    9: DB   9, 10");
+    }
+}
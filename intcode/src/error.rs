@@ -0,0 +1,67 @@
+use std::error::Error;
+use std::fmt;
+
+// Where the VM was when something went wrong.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Fault {
+    pub ip: i64,
+    pub op: i64,
+    pub word: i64,
+    pub base: i64
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOp(i64),
+    UnknownMode(i64)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VmError {
+    UnknownOp { fault: Fault },
+    UnknownMode { mode: i64, fault: Fault },
+    ImmediateWrite { fault: Fault },
+    NegativeAddress { address: i64, fault: Fault },
+    MemoryLimit { address: i64, limit: usize, fault: Fault },
+    Overflow { fault: Fault },
+    OutOfFuel { fault: Fault }
+}
+
+impl VmError {
+    pub fn decode(error: DecodeError, fault: Fault) -> VmError {
+        match error {
+            DecodeError::UnknownOp(_) => VmError::UnknownOp { fault },
+            DecodeError::UnknownMode(mode) => VmError::UnknownMode { mode, fault }
+        }
+    }
+
+    pub fn fault(&self) -> &Fault {
+        match self {
+            VmError::UnknownOp { fault } => fault,
+            VmError::UnknownMode { fault, .. } => fault,
+            VmError::ImmediateWrite { fault } => fault,
+            VmError::NegativeAddress { fault, .. } => fault,
+            VmError::MemoryLimit { fault, .. } => fault,
+            VmError::Overflow { fault } => fault,
+            VmError::OutOfFuel { fault } => fault
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::UnknownOp { fault } => write!(f, "no instruction for op {}", fault.op)?,
+            VmError::UnknownMode { mode, .. } => write!(f, "unrecognised mode {}", mode)?,
+            VmError::ImmediateWrite { .. } => write!(f, "write to an immediate-mode parameter")?,
+            VmError::NegativeAddress { address, .. } => write!(f, "access to negative address {}", address)?,
+            VmError::MemoryLimit { address, limit, .. } => write!(f, "write to {} beyond the memory limit of {} words", address, limit)?,
+            VmError::Overflow { .. } => write!(f, "arithmetic overflow")?,
+            VmError::OutOfFuel { .. } => write!(f, "out of fuel")?
+        }
+        let fault = self.fault();
+        write!(f, " at ip {} (word {}, base {})", fault.ip, fault.word, fault.base)
+    }
+}
+
+impl Error for VmError {}
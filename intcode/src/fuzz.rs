@@ -0,0 +1,337 @@
+use std::any::Any;
+use std::collections::HashSet;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::conformance::{self, Backend, BACKENDS};
+use crate::{argc, Memory, Vm, VmError, OP_HALT};
+
+// xorshift64*: small, fast and reproducible from a seed, which is all a fuzzer needs.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // spread the seed's bits out; the state must never be zero
+        let state = (seed ^ 0x9e37_79b9_7f4a_7c15).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        Rng(if state == 0 { 1 } else { state })
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Uniform in 0..n; n must not be zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    // Uniform in low..high, which mustn't be empty. The width is worked out unsigned, so the
+    // range can span all of i64.
+    pub fn range(&mut self, low: i64, high: i64) -> i64 {
+        assert!(low < high, "empty range {}..{}", low, high);
+        low.wrapping_add(self.below(high.abs_diff(low)) as i64)
+    }
+
+    pub fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+}
+
+const OPS: [i64; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, OP_HALT];
+
+// Mostly-valid code with operands pointing back into the program, so runs get somewhere.
+pub fn generate(rng: &mut Rng, len: usize) -> Vec<i64> {
+    let mut program = vec!();
+    while program.len() < len {
+        if rng.chance(5) {
+            program.push(interesting(rng, len));
+            continue;
+        }
+        let op = OPS[rng.below(OPS.len() as u64) as usize];
+        let argc = argc(op).unwrap();
+        let modes = (0..argc).fold(0, |modes, _| modes * 10 + rng.range(0, 3));
+        program.push(modes * 100 + op);
+        for _ in 0..argc {
+            program.push(operand(rng, len));
+        }
+    }
+    program
+}
+
+fn operand(rng: &mut Rng, len: usize) -> i64 {
+    if rng.chance(90) {
+        rng.range(-2, len as i64 + 4)
+    } else {
+        interesting(rng, len)
+    }
+}
+
+// Values near the edges, where off-by-ones and overflows live.
+fn interesting(rng: &mut Rng, len: usize) -> i64 {
+    let values = [0, 1, -1, 99, len as i64, i64::MAX, i64::MIN, i64::MAX / 2 + 1, 1 << 32, 1 << 24, -(1 << 24)];
+    values[rng.below(values.len() as u64) as usize].wrapping_add(rng.range(-1, 2))
+}
+
+pub fn mutate(rng: &mut Rng, program: &[i64]) -> Vec<i64> {
+    let mut program = program.to_vec();
+    let len = program.len().max(1);
+    for _ in 0..=rng.below(3) {
+        let at = rng.below(len as u64) as usize;
+        match rng.below(5) {
+            0 if at < program.len() => program[at] = operand(rng, len),
+            1 if at < program.len() => program[at] = program[at].wrapping_add(rng.range(-2, 3) * if rng.chance(50) { 1 } else { 100 }),
+            2 => program.insert(at.min(program.len()), generate(rng, 1)[0]),
+            3 if program.len() > 1 && at < program.len() => { program.remove(at); },
+            _ => program.extend(generate(rng, 4))
+        }
+    }
+    program
+}
+
+// Everything about a finished run that every backend should agree on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Observed {
+    pub result: Result<(), VmError>,
+    pub outputs: Vec<i64>,
+    pub ip: i64,
+    pub base: i64,
+    pub halted: bool,
+    pub memory: Option<Memory>
+}
+
+impl Observed {
+    fn new(vm: &Vm, result: Result<(), VmError>) -> Observed {
+        Observed { result, outputs: vm.outputs.clone(), ip: vm.ip, base: vm.base, halted: vm.halted(), memory: Some(vm.memory.clone()) }
+    }
+
+    // When a backend fails, only the error itself is known.
+    fn failed(error: VmError) -> Observed {
+        Observed { result: Err(error), outputs: vec!(), ip: error.fault().ip, base: error.fault().base, halted: false, memory: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    // A backend finished differently from the interpreter.
+    Disagreement { backend: String, expected: Box<Observed>, got: Box<Observed> },
+    // Something panicked rather than returning a VmError.
+    Panic { backend: String, message: String },
+    // The interpreter finished, but these backends ran out of steps before they did.
+    Hang { backends: Vec<String> }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub program: Vec<i64>,
+    pub inputs: Vec<i64>,
+    pub finding: Finding
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.finding {
+            Finding::Disagreement { backend, expected, got } =>
+                writeln!(f, "{} disagrees with the interpreter\n  expected {:?}\n  got      {:?}", backend, expected, got)?,
+            Finding::Panic { backend, message } => writeln!(f, "{} panicked: {}", backend, message)?,
+            Finding::Hang { backends } => writeln!(f, "{:?} still running when the interpreter had finished", backends)?
+        }
+        let program: Vec<String> = self.program.iter().map(|w| w.to_string()).collect();
+        write!(f, "  program {}\n  inputs  {:?}", program.join(","), self.inputs)
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic".to_string())
+}
+
+// What the interpreter saw: the reference result, plus the (previous word, word) pairs it
+// executed as coverage.
+fn reference(program: &[i64], inputs: &[i64], budget: usize, features: &mut HashSet<(i64, i64)>) -> Option<Observed> {
+    let mut vm = conformance::machine(program, inputs);
+    let mut previous = -1;
+    for _ in 0..budget {
+        // garbage words all count as one feature, or every random constant would look new
+        let word = Some(vm.peek(vm.ip)).filter(|word| (0..30000).contains(word) && argc(word % 100).is_some()).unwrap_or(-1);
+        features.insert((previous, word));
+        previous = word;
+        match vm.step() {
+            Ok(true) => {},
+            Ok(false) => return Some(Observed::new(&vm, Ok(()))),
+            Err(e) => {
+                // the error kind counts as coverage too
+                features.insert((word, -error_kind(&e)));
+                return Some(Observed::new(&vm, Err(e)));
+            }
+        }
+    }
+    None
+}
+
+fn error_kind(error: &VmError) -> i64 {
+    match error {
+        VmError::UnknownOp { .. } => 1,
+        VmError::UnknownMode { .. } => 2,
+        VmError::ImmediateWrite { .. } => 3,
+        VmError::NegativeAddress { .. } => 4,
+        VmError::MemoryLimit { .. } => 5,
+        VmError::Overflow { .. } => 6,
+        VmError::OutOfFuel { .. } => 7
+    }
+}
+
+pub struct Fuzzer {
+    pub rng: Rng,
+    pub corpus: Vec<(Vec<i64>, Vec<i64>)>,
+    pub coverage: HashSet<(i64, i64)>,
+    // Steps a program gets before it's written off as looping, on every backend.
+    pub budget: usize,
+    pub executions: u64,
+    pub findings: Vec<Report>
+}
+
+impl Fuzzer {
+    pub fn new(seed: u64) -> Fuzzer {
+        Fuzzer {
+            rng: Rng::new(seed),
+            corpus: vec!(),
+            coverage: HashSet::new(),
+            budget: 10_000,
+            executions: 0,
+            findings: vec!()
+        }
+    }
+
+    // Runs one program on every backend and records anything that went wrong. Returns whether
+    // it reached code the fuzzer hadn't seen before.
+    pub fn check(&mut self, program: &[i64], inputs: &[i64]) -> bool {
+        self.executions += 1;
+        let mut features = HashSet::new();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| reference(program, inputs, self.budget, &mut features)));
+        let new_coverage = features.iter().any(|feature| !self.coverage.contains(feature));
+        self.coverage.extend(features);
+
+        let report = |finding| Report { program: program.to_vec(), inputs: inputs.to_vec(), finding };
+        let expected = match result {
+            Ok(Some(expected)) => expected,
+            Ok(None) => return new_coverage,
+            Err(payload) => {
+                self.findings.push(report(Finding::Panic { backend: "Interpreter".to_string(), message: panic_message(payload) }));
+                return new_coverage;
+            }
+        };
+
+        // the big-integer machine never overflows, and would only grind on through ever bigger
+        // numbers where the interpreter stopped
+        let overflowed = matches!(expected.result, Err(VmError::Overflow { .. }));
+        let backends: Vec<Backend> = BACKENDS.iter().skip(1).copied()
+            .filter(|backend| !(overflowed && *backend == Backend::Big))
+            .collect();
+
+        // every backend gets the steps the interpreter had, so one that never stops runs out of
+        // fuel instead of hanging the fuzzer
+        let mut hung = vec!();
+        for backend in backends {
+            let name = format!("{:?}", backend);
+            let mut vm = conformance::machine(program, inputs);
+            vm.fuel = Some(self.budget as u64);
+            let result = panic::catch_unwind(AssertUnwindSafe(|| match conformance::run_on(backend, vm) {
+                Ok(vm) => Observed::new(&vm, Ok(())),
+                Err(e) => Observed::failed(e)
+            }));
+            let got = match result {
+                Ok(got) => got,
+                Err(payload) => {
+                    self.findings.push(report(Finding::Panic { backend: name, message: panic_message(payload) }));
+                    continue;
+                }
+            };
+            if let Err(VmError::OutOfFuel { .. }) = got.result {
+                hung.push(name);
+            } else if let Some(finding) = compare(&name, &expected, got) {
+                self.findings.push(report(finding));
+            }
+        }
+        if !hung.is_empty() {
+            self.findings.push(report(Finding::Hang { backends: hung }));
+        }
+        new_coverage
+    }
+
+    // Generates or mutates `iterations` programs, keeping the ones that found new coverage to
+    // mutate further.
+    pub fn run(&mut self, iterations: u64) {
+        for _ in 0..iterations {
+            let program = if self.corpus.is_empty() || self.rng.chance(20) {
+                let len = self.rng.range(4, 48) as usize;
+                generate(&mut self.rng, len)
+            } else {
+                let pick = self.rng.below(self.corpus.len() as u64) as usize;
+                mutate(&mut self.rng, &self.corpus[pick].0)
+            };
+            let inputs: Vec<i64> = (0..self.rng.below(4)).map(|_| operand(&mut self.rng, program.len())).collect();
+            if self.check(&program, &inputs) {
+                self.corpus.push((program, inputs));
+            }
+        }
+    }
+}
+
+fn compare(backend: &str, expected: &Observed, got: Observed) -> Option<Finding> {
+    let agrees = if got.result.is_err() {
+        got.result == expected.result
+    } else {
+        got == *expected
+    };
+
+    if agrees {
+        None
+    } else {
+        Some(Finding::Disagreement { backend: backend.to_string(), expected: Box::new(expected.clone()), got: Box::new(got) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_is_reproducible() {
+        let (mut a, mut b) = (Rng::new(7), Rng::new(7));
+        let first: Vec<u64> = (0..5).map(|_| a.next_u64()).collect();
+        assert_eq!(first, (0..5).map(|_| b.next_u64()).collect::<Vec<u64>>());
+        assert_ne!(Rng::new(0).next_u64(), 0);
+        assert!((0..1000).all(|_| (-2..3).contains(&a.range(-2, 3))));
+        assert!((0..1000).all(|_| a.range(i64::MIN, i64::MAX) != i64::MAX));
+        assert!((0..1000).all(|_| (i64::MAX - 2..i64::MAX).contains(&a.range(i64::MAX - 2, i64::MAX))));
+        assert_eq!(a.range(i64::MIN, i64::MIN + 1), i64::MIN);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty range 3..3")]
+    fn test_empty_range() {
+        Rng::new(1).range(3, 3);
+    }
+
+    #[test]
+    fn test_finds_nothing_in_agreeing_backends() {
+        let mut fuzzer = Fuzzer::new(2019);
+        fuzzer.run(300);
+        assert!(fuzzer.findings.is_empty(), "{}", fuzzer.findings.iter().map(|f| f.to_string()).collect::<Vec<String>>().join("\n\n"));
+        assert!(fuzzer.coverage.len() > 100);
+        assert!(!fuzzer.corpus.is_empty());
+    }
+
+    #[test]
+    fn test_reports_disagreements() {
+        let expected = Observed { result: Ok(()), outputs: vec!(1), ip: 2, base: 0, halted: true, memory: None };
+        let got = Observed { outputs: vec!(2), ..expected.clone() };
+        assert!(compare("Compiled", &expected, expected.clone()).is_none());
+        assert!(matches!(compare("Compiled", &expected, got), Some(Finding::Disagreement { .. })));
+    }
+}
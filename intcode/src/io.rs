@@ -0,0 +1,30 @@
+use std::collections::VecDeque;
+
+// Something the VM can talk to through its IN and OUT instructions.
+// Returning None from input pauses the VM on the IN instruction so it can be resumed later.
+pub trait IoDevice {
+    fn input(&mut self) -> Option<i64>;
+    fn output(&mut self, v: i64);
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Queue {
+    pub inputs: VecDeque<i64>,
+    pub outputs: Vec<i64>
+}
+
+impl Queue {
+    pub fn new(inputs: Vec<i64>) -> Queue {
+        Queue { inputs: inputs.into(), outputs: vec!() }
+    }
+}
+
+impl IoDevice for Queue {
+    fn input(&mut self) -> Option<i64> {
+        self.inputs.pop_front()
+    }
+
+    fn output(&mut self, v: i64) {
+        self.outputs.push(v);
+    }
+}
@@ -0,0 +1,807 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem;
+use std::num::ParseIntError;
+use std::sync::Arc;
+
+pub mod arcade;
+pub mod ascii;
+pub mod asm;
+pub mod bignum;
+pub mod cache;
+pub mod cfg;
+pub mod compile;
+pub mod conformance;
+pub mod debugger;
+pub mod disasm;
+pub mod fuzz;
+mod error;
+mod io;
+pub mod memory;
+pub mod network;
+pub mod profile;
+pub mod search;
+pub mod snapshot;
+pub mod threaded;
+pub mod trace;
+
+pub use error::{DecodeError, Fault, VmError};
+pub use io::{IoDevice, Queue};
+pub use memory::Memory;
+use memory::MemoryError;
+use cache::DecodeCache;
+use compile::Compiled;
+use profile::Profile;
+use trace::{Record, Tracer};
+
+pub type Storage = Vec<i64>;
+
+pub const OP_ADD: i64 = 1;
+pub const OP_MUL: i64 = 2;
+pub const OP_IN: i64 = 3;
+pub const OP_OUT: i64 = 4;
+pub const OP_JNZ: i64 = 5;
+pub const OP_JZ: i64 = 6;
+pub const OP_WLT: i64 = 7;
+pub const OP_WEQ: i64 = 8;
+pub const OP_BASE: i64 = 9;
+pub const OP_HALT: i64 = 99;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    Imm,
+    Base
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Arg {
+    pub value: i64,
+    pub mode: Mode
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    Add { add1: Arg, add2: Arg, dest: Arg },
+    Mul { mul1: Arg, mul2: Arg, dest: Arg },
+    In { dest: Arg },
+    Out { data: Arg },
+    Jnz { test: Arg, abs_target: Arg },
+    Jz { test: Arg, abs_target: Arg },
+    WriteLess { test_a: Arg, test_b:  Arg, dest: Arg },
+    WriteEqual { test_a: Arg, test_b: Arg, dest: Arg },
+    SetBase { base: Arg },
+    Halt
+}
+
+pub fn parse_program(input: &str) -> Result<Storage, ParseIntError> {
+    input.trim().split(',')
+        .map(|tok| tok.trim().parse::<i64>())
+        .collect()
+}
+
+pub fn mnemonic(op: i64) -> Option<&'static str> {
+    match op {
+        OP_ADD => Some("ADD"),
+        OP_MUL => Some("MUL"),
+        OP_IN => Some("IN"),
+        OP_OUT => Some("OUT"),
+        OP_JNZ => Some("JNZ"),
+        OP_JZ => Some("JZ"),
+        OP_WLT => Some("LT"),
+        OP_WEQ => Some("EQ"),
+        OP_BASE => Some("ARB"),
+        OP_HALT => Some("HLT"),
+        _ => None
+    }
+}
+
+pub fn argc(op: i64) -> Option<i64> {
+    match op {
+        OP_HALT => Some(0),
+        OP_IN | OP_OUT | OP_BASE => Some(1),
+        OP_JNZ | OP_JZ => Some(2),
+        OP_ADD | OP_MUL | OP_WLT | OP_WEQ => Some(3),
+        _ => None
+    }
+}
+
+fn mode(digit: i64) -> Result<Mode, DecodeError> {
+    match digit {
+        0 => Ok(Mode::Normal),
+        1 => Ok(Mode::Imm),
+        2 => Ok(Mode::Base),
+        m => Err(DecodeError::UnknownMode(m))
+    }
+}
+
+pub fn arg_modes(op: i64, mut arginfo: i64) -> Result<Vec<Mode>, DecodeError> {
+    let mut arg_modes = vec!();
+
+    for _ in 0..argc(op).ok_or(DecodeError::UnknownOp(op))? {
+        arg_modes.push(mode(arginfo % 10)?);
+        arginfo /= 10;
+    }
+
+    Ok(arg_modes)
+}
+
+// Decodes the instruction at ip, reading the word and its parameters through memory.
+pub fn decode(memory: impl Fn(i64) -> i64, ip: i64) -> Result<Instruction, DecodeError> {
+    let word = memory(ip);
+    let op = word % 100;
+    let argc = argc(op).ok_or(DecodeError::UnknownOp(op))?;
+
+    let mut modes = [Mode::Normal; 3];
+    let mut arginfo = word / 100;
+    for m in modes.iter_mut().take(argc as usize) {
+        *m = mode(arginfo % 10)?;
+        arginfo /= 10;
+    }
+    let arg = |i: usize| Arg { mode: modes[i], value: memory(ip + i as i64 + 1) };
+
+    Ok(match op {
+        OP_ADD => Instruction::Add { add1: arg(0), add2: arg(1), dest: arg(2) },
+        OP_MUL => Instruction::Mul { mul1: arg(0), mul2: arg(1), dest: arg(2) },
+        OP_IN => Instruction::In { dest: arg(0) },
+        OP_OUT => Instruction::Out { data: arg(0) },
+        OP_JNZ => Instruction::Jnz { test: arg(0), abs_target: arg(1) },
+        OP_JZ => Instruction::Jz { test: arg(0), abs_target: arg(1) },
+        OP_WLT => Instruction::WriteLess { test_a: arg(0), test_b: arg(1), dest: arg(2) },
+        OP_WEQ => Instruction::WriteEqual { test_a: arg(0), test_b: arg(1), dest: arg(2) },
+        OP_BASE => Instruction::SetBase { base: arg(0) },
+        OP_HALT => Instruction::Halt,
+        _ => return Err(DecodeError::UnknownOp(op))
+    })
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            Mode::Normal => write!(f, "[{}]", self.value),
+            Mode::Imm => write!(f, "#{}", self.value),
+            Mode::Base if self.value < 0 => write!(f, "[rb{}]", self.value),
+            Mode::Base => write!(f, "[rb+{}]", self.value)
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mnemonic = mnemonic(self.op()).expect("every instruction has a mnemonic");
+        let args = self.args();
+        if args.is_empty() {
+            return write!(f, "{}", mnemonic);
+        }
+        write!(f, "{:<4}", mnemonic)?;
+        for (i, arg) in args.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, arg)?;
+        }
+        Ok(())
+    }
+}
+
+impl Instruction {
+    pub fn args(&self) -> Vec<Arg> {
+        match *self {
+            Instruction::Add { add1, add2, dest } => vec!(add1, add2, dest),
+            Instruction::Mul { mul1, mul2, dest } => vec!(mul1, mul2, dest),
+            Instruction::In { dest } => vec!(dest),
+            Instruction::Out { data } => vec!(data),
+            Instruction::Jnz { test, abs_target } => vec!(test, abs_target),
+            Instruction::Jz { test, abs_target } => vec!(test, abs_target),
+            Instruction::WriteLess { test_a, test_b, dest } => vec!(test_a, test_b, dest),
+            Instruction::WriteEqual { test_a, test_b, dest } => vec!(test_a, test_b, dest),
+            Instruction::SetBase { base } => vec!(base),
+            Instruction::Halt => vec!()
+        }
+    }
+
+    // Number of words the instruction takes up, including the opcode word.
+    pub fn size(&self) -> i64 {
+        self.argc() + 1
+    }
+
+    pub fn op(&self) -> i64 {
+        match self {
+            Instruction::Add { .. } => OP_ADD,
+            Instruction::Mul { .. } => OP_MUL,
+            Instruction::In { .. } => OP_IN,
+            Instruction::Out { .. } => OP_OUT,
+            Instruction::Jnz { .. } => OP_JNZ,
+            Instruction::Jz { .. } => OP_JZ,
+            Instruction::WriteLess { .. } => OP_WLT,
+            Instruction::WriteEqual { .. } => OP_WEQ,
+            Instruction::SetBase { .. } => OP_BASE,
+            Instruction::Halt => OP_HALT
+        }
+    }
+
+    pub fn argc(&self) -> i64 {
+        argc(self.op()).expect("every instruction has an argc")
+    }
+
+    // The opcode word, with the parameter modes encoded above the op.
+    pub fn word(&self) -> i64 {
+        let mut word = self.op();
+        let mut scale = 100;
+        for arg in self.args() {
+            word += scale * match arg.mode {
+                Mode::Normal => 0,
+                Mode::Imm => 1,
+                Mode::Base => 2
+            };
+            scale *= 10;
+        }
+        word
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    NeedsInput,
+    Output(i64),
+    Halted
+}
+
+// Collects inputs for run_until_event and holds on to the first output so the VM can stop on it.
+struct EventIo {
+    inputs: VecDeque<i64>,
+    output: Option<i64>
+}
+
+impl IoDevice for EventIo {
+    fn input(&mut self) -> Option<i64> {
+        self.inputs.pop_front()
+    }
+
+    fn output(&mut self, v: i64) {
+        self.output = Some(v);
+    }
+}
+
+// What ADD and MUL do when the result doesn't fit in a word.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Arithmetic {
+    // Two's complement wraparound, as the machine has always done in release builds.
+    #[default]
+    Wrap,
+    // Stop with VmError::Overflow, leaving the machine on the instruction.
+    Trap,
+    // Stop like Trap, so that bignum::run can carry on in arbitrary precision.
+    Promote
+}
+
+pub struct Vm {
+    pub ip: i64,
+    pub memory: Memory,
+    pub base: i64,
+    pub inputs: VecDeque<i64>,
+    pub outputs: Vec<i64>,
+    pub debug: u8,
+    pub arithmetic: Arithmetic,
+    // Instructions left before the machine stops with VmError::OutOfFuel; None never runs out.
+    pub fuel: Option<u64>,
+    pub tracer: Option<Box<dyn Tracer>>,
+    pub profile: Option<Profile>,
+    pub cache: Option<DecodeCache>,
+    compiled: Option<Arc<Compiled>>,
+    record: Option<Record>
+}
+
+impl Vm {
+
+    pub fn new(storage: Storage) -> Vm {
+        Vm {
+            ip: 0,
+            memory: Memory::from(storage),
+            base: 0,
+            inputs: VecDeque::new(),
+            outputs: vec!(),
+            debug: 0,
+            arithmetic: Arithmetic::default(),
+            fuel: None,
+            tracer: None,
+            profile: None,
+            cache: None,
+            compiled: None,
+            record: None
+        }
+    }
+
+    pub fn with_inputs(storage: Storage, inputs: Vec<i64>) -> Vm {
+        let mut vm = Vm::new(storage);
+        vm.inputs.extend(inputs);
+        vm
+    }
+
+    pub fn push_input(&mut self, value: i64) {
+        self.inputs.push_back(value);
+    }
+
+    pub fn read(&self, address: i64) -> Result<i64, VmError> {
+        if address < 0 {
+            return Err(VmError::NegativeAddress { address, fault: self.fault() });
+        }
+
+        Ok(self.peek(address))
+    }
+
+    // Reads memory without any checks; negative addresses read as 0.
+    pub fn peek(&self, address: i64) -> i64 {
+        self.memory.get(address)
+    }
+
+    pub fn write(&mut self, address: i64, value: i64) -> Result<(), VmError> {
+        match self.memory.set(address, value) {
+            Ok(()) => {},
+            Err(MemoryError::NegativeAddress) =>
+                return Err(VmError::NegativeAddress { address, fault: self.fault() }),
+            Err(MemoryError::OverLimit) =>
+                return Err(VmError::MemoryLimit { address, limit: self.memory.limit(), fault: self.fault() })
+        }
+
+        if let Some(cache) = &mut self.cache {
+            cache.invalidate(address);
+        }
+        if self.compiled.as_ref().is_some_and(|compiled| compiled.covers(address)) {
+            self.compiled = None;
+        }
+        if let Some(record) = &mut self.record {
+            record.writes.push((address, value));
+        }
+
+        Ok(())
+    }
+
+    pub fn fault(&self) -> Fault {
+        let word = self.peek(self.ip);
+        Fault { ip: self.ip, op: word % 100, word, base: self.base }
+    }
+
+    // Runs until the program halts or asks for input that hasn't been pushed yet.
+    pub fn run(&mut self) -> Result<(), VmError> {
+        self.with_queue(|vm, queue| vm.run_with(queue))
+    }
+
+    // Runs until the program halts or the device has no input to give.
+    pub fn run_with<D: IoDevice + ?Sized>(&mut self, io: &mut D) -> Result<(), VmError> {
+        if self.debug >= 1 {
+            println!("{:?}", self.memory);
+        }
+        if let Some(compiled) = self.compiled.clone() {
+            if self.tracer.is_none() && self.profile.is_none() {
+                return self.run_compiled(compiled, io);
+            }
+        }
+        while self.step_with(io)? {}
+        Ok(())
+    }
+
+    // Runs until the next output, until the program halts, or until it needs input that hasn't
+    // been pushed yet. Outputs are handed back here rather than collected in `outputs`.
+    pub fn run_until_event(&mut self) -> Result<Event, VmError> {
+        let mut io = EventIo { inputs: mem::take(&mut self.inputs), output: None };
+        let event = loop {
+            match self.step_with(&mut io) {
+                Ok(false) => break Ok(if self.halted() { Event::Halted } else { Event::NeedsInput }),
+                Ok(true) => if let Some(v) = io.output.take() {
+                    break Ok(Event::Output(v));
+                },
+                Err(e) => break Err(e)
+            }
+        };
+        self.inputs = io.inputs;
+        event
+    }
+
+    // Lends the VM's own input and output buffers out as a device.
+    fn with_queue<T>(&mut self, f: impl FnOnce(&mut Vm, &mut Queue) -> T) -> T {
+        let mut queue = Queue {
+            inputs: mem::take(&mut self.inputs),
+            outputs: mem::take(&mut self.outputs)
+        };
+        let result = f(self, &mut queue);
+        self.inputs = queue.inputs;
+        self.outputs = queue.outputs;
+        result
+    }
+
+    pub fn halted(&self) -> bool {
+        self.peek(self.ip) % 100 == OP_HALT
+    }
+
+    pub fn needs_input(&self) -> bool {
+        self.peek(self.ip) % 100 == OP_IN && self.inputs.is_empty()
+    }
+
+    fn advance_ip(&mut self, inc: i64) {
+        self.ip += inc;
+    }
+
+    pub fn next_instruction(&self) -> Result<Instruction, VmError> {
+        self.read(self.ip)?;
+        decode(|address| self.peek(address), self.ip).map_err(|e| VmError::decode(e, self.fault()))
+    }
+
+    // Like next_instruction, but goes through the decode cache when there is one.
+    #[inline]
+    fn fetch(&mut self) -> Result<Instruction, VmError> {
+        let ip = self.ip;
+        match &mut self.cache {
+            None => self.next_instruction(),
+            Some(cache) => match cache.get(ip) {
+                Some(instruction) => Ok(instruction),
+                None => {
+                    let instruction = self.next_instruction()?;
+                    let cacheable = (ip as usize) < self.memory.limit();
+                    if let Some(cache) = self.cache.as_mut().filter(|_| cacheable) {
+                        cache.insert(ip, instruction);
+                    }
+                    Ok(instruction)
+                }
+            }
+        }
+    }
+
+    // Returns false once the VM has halted, or if the device has no input to give.
+    // In the latter case the ip is left on the input instruction so a later run resumes it.
+    pub fn execute<D: IoDevice + ?Sized>(&mut self, instruction: Instruction, io: &mut D) -> Result<bool, VmError> {
+        if self.tracer.is_none() && self.profile.is_none() {
+            return self.apply(instruction, io);
+        }
+
+        let ip = self.ip;
+        if self.tracer.is_some() {
+            self.record = Some(Record::new(ip, instruction));
+        }
+        let result = self.apply(instruction, io);
+        let record = self.record.take();
+        // HLT runs too, it just stops the machine; an input stall runs again on the next resume
+        if result == Ok(true) || (result == Ok(false) && instruction == Instruction::Halt) {
+            if let Some(profile) = &mut self.profile {
+                profile.count(ip, &instruction, self.ip);
+            }
+            if let (Some(tracer), Some(mut record)) = (&mut self.tracer, record) {
+                record.base = self.base;
+                tracer.record(&record);
+            }
+        }
+        result
+    }
+
+    fn apply<D: IoDevice + ?Sized>(&mut self, instruction: Instruction, io: &mut D) -> Result<bool, VmError> {
+        let size = instruction.size();
+
+        match instruction {
+            Instruction::Add { add1, add2, dest } => {
+                let (a, b) = (self.resolve_param(&add1)?, self.resolve_param(&add2)?);
+                let result = self.add(a, b)?;
+                let address = self.resolve_param_w(&dest)?;
+                self.write(address, result)?;
+                self.advance_ip(size);
+            },
+            Instruction::Mul { mul1, mul2, dest } => {
+                let (a, b) = (self.resolve_param(&mul1)?, self.resolve_param(&mul2)?);
+                let result = self.mul(a, b)?;
+                let address = self.resolve_param_w(&dest)?;
+                self.write(address, result)?;
+                self.advance_ip(size);
+            },
+            Instruction::In { dest } => {
+                let address = self.resolve_param_w(&dest)?;
+                let input = match io.input() {
+                    Some(input) => input,
+                    None => return Ok(false)
+                };
+                self.write(address, input)?;
+                self.advance_ip(size);
+            },
+            Instruction::Out { data } => {
+                let value = self.resolve_param(&data)?;
+                io.output(value);
+                self.advance_ip(size);
+            },
+            Instruction::Jnz { test, abs_target } => {
+                if self.resolve_param(&test)? == 0 {
+                    self.advance_ip(size);
+                } else {
+                    self.ip = self.resolve_param(&abs_target)?;
+                }
+            },
+            Instruction::Jz { test, abs_target } => {
+                if self.resolve_param(&test)? == 0 {
+                    self.ip = self.resolve_param(&abs_target)?;
+                } else {
+                    self.advance_ip(size);
+                }
+            },
+            Instruction::WriteLess { test_a, test_b, dest } => {
+                let a = self.resolve_param(&test_a)?;
+                let b = self.resolve_param(&test_b)?;
+                let address = self.resolve_param_w(&dest)?;
+                self.write(address, if a < b { 1 } else { 0 })?;
+                self.advance_ip(size);
+            },
+            Instruction::WriteEqual { test_a, test_b, dest } => {
+                let a = self.resolve_param(&test_a)?;
+                let b = self.resolve_param(&test_b)?;
+                let address = self.resolve_param_w(&dest)?;
+                self.write(address, if a == b { 1 } else { 0 })?;
+                self.advance_ip(size);
+            },
+            Instruction::SetBase { base } => {
+                let offset = self.resolve_param(&base)?;
+                self.base = self.add(self.base, offset)?;
+                self.advance_ip(size)
+            },
+            Instruction::Halt => {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub(crate) fn add(&self, a: i64, b: i64) -> Result<i64, VmError> {
+        self.overflow(a.checked_add(b), || a.wrapping_add(b))
+    }
+
+    pub(crate) fn mul(&self, a: i64, b: i64) -> Result<i64, VmError> {
+        self.overflow(a.checked_mul(b), || a.wrapping_mul(b))
+    }
+
+    fn overflow(&self, checked: Option<i64>, wrapped: impl FnOnce() -> i64) -> Result<i64, VmError> {
+        match (checked, self.arithmetic) {
+            (Some(result), _) => Ok(result),
+            (None, Arithmetic::Wrap) => Ok(wrapped()),
+            (None, _) => Err(VmError::Overflow { fault: self.fault() })
+        }
+    }
+
+    pub fn step(&mut self) -> Result<bool, VmError> {
+        self.with_queue(|vm, queue| vm.step_with(queue))
+    }
+
+    pub fn step_with<D: IoDevice + ?Sized>(&mut self, io: &mut D) -> Result<bool, VmError> {
+        self.burn()?;
+        let i = self.fetch()?;
+        if self.debug >= 1 {
+            println!("{:?}", i);
+        }
+        self.execute(i, io)
+    }
+
+    // Uses up the fuel for one instruction, leaving the machine on it if there's none left.
+    #[inline]
+    pub(crate) fn burn(&mut self) -> Result<(), VmError> {
+        match self.fuel {
+            Some(0) => return Err(VmError::OutOfFuel { fault: self.fault() }),
+            Some(fuel) => self.fuel = Some(fuel - 1),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn resolve_param(&mut self, arg: &Arg) -> Result<i64, VmError> {
+        let value = match arg.mode {
+            Mode::Normal => self.read(arg.value)?,
+            Mode::Imm => arg.value,
+            Mode::Base => self.read(self.add(self.base, arg.value)?)?
+        };
+        if let Some(record) = &mut self.record {
+            record.operands.push(value);
+        }
+        Ok(value)
+    }
+
+    fn resolve_param_w(&self, arg: &Arg) -> Result<i64, VmError> {
+        match arg.mode {
+            Mode::Normal => Ok(arg.value),
+            Mode::Imm => Err(VmError::ImmediateWrite { fault: self.fault() }),
+            Mode::Base => self.add(self.base, arg.value)
+        }
+    }
+
+    pub fn dump(&self) {
+        println!("memory: {:?}", self.memory);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: Vec<i64>, inputs: Vec<i64>) -> Vm {
+        let mut vm = Vm::with_inputs(program, inputs);
+        vm.run().unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_example1() {
+        let vm = run(vec!(1002,4,3,4,33), vec!(1));
+        assert_eq!(vm.memory.image(5), vec!(1002,4,3,4,99));
+    }
+
+    #[test]
+    fn test_op1() {
+        let vm = run(vec!(1001,4,2,0,99), vec!(1));
+        assert_eq!(vm.memory.image(5), vec!(101,4,2,0,99));
+    }
+
+    #[test]
+    fn test_op2() {
+        let vm = run(vec!(1002,4,2,0,99), vec!(1));
+        assert_eq!(vm.memory.image(5), vec!(198,4,2,0,99));
+    }
+
+    #[test]
+    fn test_op3() {
+        let vm = run(vec!(3,3,99,0), vec!(-4));
+        assert_eq!(vm.memory.image(4), vec!(3,3,99,-4));
+    }
+
+    #[test]
+    fn test_position_je() {
+        let vm = run(vec!(3,9,8,9,10,9,4,9,99,-1,8), vec!(1));
+        assert_eq!(vm.outputs, vec!(0));
+    }
+
+    #[test]
+    fn test_position_je2() {
+        let vm = run(vec!(3,9,8,9,10,9,4,9,99,-1,8), vec!(8));
+        assert_eq!(vm.outputs, vec!(1));
+    }
+
+    #[test]
+    fn failing_code1() {
+        let vm = run(vec!(109,5,203,0,99,0), vec!(1));
+        vm.dump();
+        assert_eq!(vm.peek(5), 1);
+    }
+
+    #[test]
+    fn test_more_storage() {
+        let vm = run(vec!(1101,3,4,100,4,100,99), vec!());
+        assert_eq!(vm.outputs, vec!(7));
+        assert_eq!(vm.peek(100), 7);
+        assert_eq!(vm.memory.pages_allocated(), 1);
+
+        let vm = run(vec!(1101,3,4,5000,4,5000,99), vec!());
+        assert_eq!(vm.outputs, vec!(7));
+        assert_eq!(vm.memory.pages_allocated(), 2);
+    }
+
+    #[test]
+    fn test_waits_for_input() {
+        let mut vm = run(vec!(3,9,4,9,3,9,4,9,99,0), vec!(5));
+        assert_eq!(vm.outputs, vec!(5));
+        assert!(vm.needs_input());
+        assert!(!vm.halted());
+
+        vm.push_input(6);
+        vm.run().unwrap();
+        assert_eq!(vm.outputs, vec!(5, 6));
+        assert!(vm.halted());
+    }
+
+    #[test]
+    fn test_run_until_event() {
+        let mut vm = Vm::new(vec!(3,9,4,9,3,9,4,9,99,0));
+        assert_eq!(vm.run_until_event().unwrap(), Event::NeedsInput);
+        assert_eq!(vm.ip, 0);
+
+        vm.push_input(5);
+        assert_eq!(vm.run_until_event().unwrap(), Event::Output(5));
+        assert_eq!(vm.run_until_event().unwrap(), Event::NeedsInput);
+        assert_eq!(vm.run_until_event().unwrap(), Event::NeedsInput);
+
+        vm.push_input(6);
+        assert_eq!(vm.run_until_event().unwrap(), Event::Output(6));
+        assert_eq!(vm.run_until_event().unwrap(), Event::Halted);
+        assert_eq!(vm.run_until_event().unwrap(), Event::Halted);
+        assert!(vm.outputs.is_empty());
+    }
+
+    #[test]
+    fn test_feedback_loop() {
+        let program = vec!(3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5);
+        let mut amps: Vec<Vm> = [9, 8, 7, 6, 5].iter()
+            .map(|phase| Vm::with_inputs(program.clone(), vec!(*phase)))
+            .collect();
+
+        let mut signal = 0;
+        'outer: loop {
+            for amp in amps.iter_mut() {
+                amp.push_input(signal);
+                match amp.run_until_event().unwrap() {
+                    Event::Output(v) => signal = v,
+                    Event::Halted => break 'outer,
+                    Event::NeedsInput => panic!("amplifier starved")
+                }
+            }
+        }
+
+        assert_eq!(signal, 139629729);
+    }
+
+    struct Doubler {
+        next: i64,
+        seen: Vec<i64>
+    }
+
+    impl IoDevice for Doubler {
+        fn input(&mut self) -> Option<i64> {
+            if self.next > 100 {
+                None
+            } else {
+                Some(self.next)
+            }
+        }
+
+        fn output(&mut self, v: i64) {
+            self.seen.push(v);
+            self.next = v * 2;
+        }
+    }
+
+    #[test]
+    fn test_run_with_device() {
+        // echo input forever
+        let mut vm = Vm::new(vec!(3,7,4,7,1105,1,0,0));
+        let mut doubler = Doubler { next: 1, seen: vec!() };
+        vm.run_with(&mut doubler).unwrap();
+        assert_eq!(doubler.seen, vec!(1, 2, 4, 8, 16, 32, 64));
+        assert!(vm.needs_input());
+        assert!(vm.outputs.is_empty());
+    }
+
+    fn fault(program: Vec<i64>) -> VmError {
+        Vm::with_inputs(program, vec!(1)).run().unwrap_err()
+    }
+
+    #[test]
+    fn test_unknown_op() {
+        let error = fault(vec!(1101,1,1,5,42,0));
+        assert_eq!(error, VmError::UnknownOp { fault: Fault { ip: 4, op: 42, word: 42, base: 0 } });
+    }
+
+    #[test]
+    fn test_unknown_mode() {
+        let error = fault(vec!(109,3,304,0,99));
+        assert_eq!(error, VmError::UnknownMode { mode: 3, fault: Fault { ip: 2, op: 4, word: 304, base: 3 } });
+        assert_eq!(error.to_string(), "unrecognised mode 3 at ip 2 (word 304, base 3)");
+    }
+
+    #[test]
+    fn test_immediate_write() {
+        let error = fault(vec!(11101,1,1,0,99));
+        assert_eq!(error, VmError::ImmediateWrite { fault: Fault { ip: 0, op: 1, word: 11101, base: 0 } });
+
+        let mut vm = Vm::with_inputs(vec!(103,0,99), vec!(7));
+        assert!(vm.run().is_err());
+        assert_eq!(vm.inputs.len(), 1);
+    }
+
+    #[test]
+    fn test_negative_address() {
+        let error = fault(vec!(4,-1,99));
+        assert_eq!(error, VmError::NegativeAddress { address: -1, fault: Fault { ip: 0, op: 4, word: 4, base: 0 } });
+
+        let error = fault(vec!(109,-5,22201,0,0,0,99));
+        assert_eq!(error, VmError::NegativeAddress { address: -5, fault: Fault { ip: 2, op: 1, word: 22201, base: -5 } });
+
+        let error = fault(vec!(1106,0,-3));
+        assert_eq!(error.fault().ip, -3);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut vm = Vm::new(vec!(1101,1,2,5000,99));
+        vm.memory.set_limit(4096);
+        let error = vm.run().unwrap_err();
+        assert_eq!(error, VmError::MemoryLimit { address: 5000, limit: 4096, fault: Fault { ip: 0, op: 1, word: 1101, base: 0 } });
+        assert_eq!(error.to_string(), "write to 5000 beyond the memory limit of 4096 words at ip 0 (word 1101, base 0)");
+    }
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+
+pub const PAGE_BITS: u32 = 10;
+pub const PAGE_SIZE: usize = 1 << PAGE_BITS;
+const PAGE_MASK: usize = PAGE_SIZE - 1;
+
+// 16M words, far beyond anything the puzzles touch, but enough to stop a runaway pointer from
+// eating the machine.
+pub const DEFAULT_LIMIT: usize = 1 << 24;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryError {
+    NegativeAddress,
+    OverLimit
+}
+
+// Word-addressed memory split into fixed-size pages that are only allocated when first written.
+// Reads of untouched memory come back as 0.
+#[derive(Clone)]
+pub struct Memory {
+    pages: Vec<Option<Box<[i64]>>>,
+    limit: usize
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory { pages: vec!(), limit: DEFAULT_LIMIT }
+    }
+
+    pub fn with_limit(limit: usize) -> Memory {
+        Memory { pages: vec!(), limit }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    // Addresses at or above the limit can't be written. Memory already written is kept.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    #[inline]
+    pub fn get(&self, address: i64) -> i64 {
+        if address < 0 {
+            return 0;
+        }
+        let address = address as usize;
+        match self.pages.get(address >> PAGE_BITS) {
+            Some(Some(page)) => page[address & PAGE_MASK],
+            _ => 0
+        }
+    }
+
+    // Fails for negative addresses and for addresses beyond the limit.
+    #[inline]
+    pub fn set(&mut self, address: i64, value: i64) -> Result<(), MemoryError> {
+        if address < 0 {
+            return Err(MemoryError::NegativeAddress);
+        }
+        if address as usize >= self.limit {
+            return Err(MemoryError::OverLimit);
+        }
+        let address = address as usize;
+        let page = address >> PAGE_BITS;
+        if page >= self.pages.len() {
+            self.pages.resize_with(page + 1, || None);
+        }
+        let page = self.pages[page].get_or_insert_with(|| vec![0; PAGE_SIZE].into_boxed_slice());
+        page[address & PAGE_MASK] = value;
+        Ok(())
+    }
+
+    // The allocated pages, as (first address, words) pairs in address order.
+    pub fn pages(&self) -> impl Iterator<Item = (i64, &[i64])> {
+        self.pages.iter().enumerate()
+            .filter_map(|(i, page)| page.as_ref().map(|page| ((i * PAGE_SIZE) as i64, &page[..])))
+    }
+
+    fn page(&self, i: usize) -> Option<&[i64]> {
+        self.pages.get(i).and_then(Option::as_deref)
+    }
+
+    pub fn pages_allocated(&self) -> usize {
+        self.pages.iter().filter(|page| page.is_some()).count()
+    }
+
+    // Copies out the words from 0 up to (but not including) end.
+    pub fn image(&self, end: usize) -> Vec<i64> {
+        (0..end as i64).map(|address| self.get(address)).collect()
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
+}
+
+impl From<Vec<i64>> for Memory {
+    fn from(program: Vec<i64>) -> Memory {
+        let mut memory = Memory::with_limit(DEFAULT_LIMIT.max(program.len()));
+        for chunk in program.chunks(PAGE_SIZE) {
+            let mut page = chunk.to_vec();
+            page.resize(PAGE_SIZE, 0);
+            memory.pages.push(Some(page.into_boxed_slice()));
+        }
+        memory
+    }
+}
+
+// Memories are equal when they have the same limit and every address reads the same, however
+// their pages happen to be allocated.
+impl PartialEq for Memory {
+    fn eq(&self, other: &Memory) -> bool {
+        self.limit == other.limit && (0..self.pages.len().max(other.pages.len())).all(|i| match (self.page(i), other.page(i)) {
+            (Some(a), Some(b)) => a == b,
+            (Some(words), None) | (None, Some(words)) => words.iter().all(|w| *w == 0),
+            (None, None) => true
+        })
+    }
+}
+
+impl Eq for Memory {}
+
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // trailing zeros in each page are noise
+        f.debug_map()
+            .entries(self.pages().map(|(start, words)| {
+                let used = words.iter().rposition(|w| *w != 0).map_or(0, |i| i + 1);
+                (start, &words[..used])
+            }))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pages_on_demand() {
+        let mut memory = Memory::from(vec!(1, 2, 3));
+        assert_eq!(memory.pages_allocated(), 1);
+        assert_eq!(memory.get(2), 3);
+        assert_eq!(memory.get(3), 0);
+        assert_eq!(memory.get(100_000), 0);
+        assert_eq!(memory.pages_allocated(), 1);
+
+        memory.set(100_000, 7).unwrap();
+        assert_eq!(memory.get(100_000), 7);
+        assert_eq!(memory.pages_allocated(), 2);
+
+        let starts: Vec<i64> = memory.pages().map(|(start, _)| start).collect();
+        assert_eq!(starts, vec!(0, (100_000 / PAGE_SIZE * PAGE_SIZE) as i64));
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut memory = Memory::with_limit(2000);
+        assert_eq!(memory.set(-1, 1), Err(MemoryError::NegativeAddress));
+        assert_eq!(memory.set(2000, 1), Err(MemoryError::OverLimit));
+        assert!(memory.set(1999, 1).is_ok());
+        assert_eq!(memory.get(-1), 0);
+        assert_eq!(memory.image(3), vec!(0, 0, 0));
+    }
+
+    #[test]
+    fn test_program_spanning_pages() {
+        let program: Vec<i64> = (0..PAGE_SIZE as i64 + 5).collect();
+        let memory = Memory::from(program.clone());
+        assert_eq!(memory.pages_allocated(), 2);
+        assert_eq!(memory.image(program.len()), program);
+        assert_eq!(format!("{:?}", Memory::from(vec!(0, 4, 0))), "{0: [0, 4]}");
+    }
+
+    #[test]
+    fn test_equal_by_content() {
+        let mut memory = Memory::from(vec!(1, 2, 3));
+        let before = memory.clone();
+        memory.set(100_000, 0).unwrap();
+        assert_eq!(memory.pages_allocated(), 2);
+        assert_eq!(memory, before);
+        memory.set(100_000, 1).unwrap();
+        assert_ne!(memory, before);
+        assert_ne!(Memory::with_limit(10), Memory::with_limit(20));
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+
+use crate::{IoDevice, Vm, VmError};
+
+pub type NodeId = usize;
+
+// Where a node's outputs go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    // Kept on the node, in `collected`.
+    Collect,
+    // Every value is copied onto the input queue of each listed node.
+    To(Vec<NodeId>),
+    // Outputs are grouped into packets of `width` words, the first being the destination node,
+    // so `width` can't be 0. Packets for addresses that aren't nodes end up in the network's
+    // `unrouted` list.
+    Packets { width: usize }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    // Each node runs for at most `slice` instructions per turn.
+    RoundRobin { slice: usize },
+    // Each node runs until it blocks, halts or polls an empty queue.
+    EventDriven
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    // Every machine has halted.
+    Halted,
+    // Some machines are still running but all of them are blocked on empty queues.
+    Deadlock,
+    // Every live machine is polling an empty queue and nothing is in flight.
+    Idle
+}
+
+// How a node's turn ended.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Turn {
+    Halted,
+    Blocked,
+    Polled,
+    Preempted
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NetworkError {
+    // A machine in the network failed.
+    Machine { node: NodeId, error: VmError },
+    // A node's route sends its outputs to a node that was never added.
+    NoSuchNode { node: NodeId, target: NodeId }
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Machine { node, error } => write!(f, "node {}: {}", node, error),
+            NetworkError::NoSuchNode { node, target } => write!(f, "node {} routes to missing node {}", node, target)
+        }
+    }
+}
+
+impl Error for NetworkError {}
+
+pub struct Node {
+    pub vm: Vm,
+    pub route: Route,
+    // Given to the machine instead of blocking when its queue is empty (e.g. -1 on a packet bus).
+    pub idle_input: Option<i64>,
+    pub collected: Vec<i64>,
+    pub last_output: Option<i64>,
+    pending: Vec<i64>
+}
+
+pub struct Network {
+    pub nodes: Vec<Node>,
+    pub queues: Vec<VecDeque<i64>>,
+    pub unrouted: Vec<Vec<i64>>,
+    pub schedule: Schedule
+}
+
+// The device a node sees while it has its turn.
+struct NodeIo<'a> {
+    queue: NodeId,
+    queues: &'a mut [VecDeque<i64>],
+    route: &'a Route,
+    pending: &'a mut Vec<i64>,
+    collected: &'a mut Vec<i64>,
+    last_output: &'a mut Option<i64>,
+    unrouted: &'a mut Vec<Vec<i64>>,
+    idle_input: Option<i64>,
+    moved: bool,
+    polled_empty: bool
+}
+
+impl<'a> IoDevice for NodeIo<'a> {
+    fn input(&mut self) -> Option<i64> {
+        match self.queues[self.queue].pop_front() {
+            Some(v) => {
+                self.moved = true;
+                Some(v)
+            },
+            None => {
+                self.polled_empty = true;
+                self.idle_input
+            }
+        }
+    }
+
+    fn output(&mut self, v: i64) {
+        self.moved = true;
+        *self.last_output = Some(v);
+        match self.route {
+            Route::Collect => self.collected.push(v),
+            Route::To(targets) => for target in targets {
+                self.queues[*target].push_back(v);
+            },
+            Route::Packets { width } => {
+                self.pending.push(v);
+                if self.pending.len() == *width {
+                    let address = self.pending[0];
+                    if address >= 0 && (address as usize) < self.queues.len() {
+                        self.queues[address as usize].extend(&self.pending[1..]);
+                    } else {
+                        self.unrouted.push(self.pending.clone());
+                    }
+                    self.pending.clear();
+                }
+            }
+        }
+    }
+}
+
+impl Network {
+    pub fn new(schedule: Schedule) -> Network {
+        Network { nodes: vec!(), queues: vec!(), unrouted: vec!(), schedule }
+    }
+
+    // Any input already pushed to the machine becomes the start of its queue.
+    pub fn add(&mut self, mut vm: Vm, route: Route) -> NodeId {
+        assert!(route != Route::Packets { width: 0 }, "packets need room for at least an address");
+        self.queues.push(mem::take(&mut vm.inputs));
+        self.nodes.push(Node { vm, route, idle_input: None, collected: vec!(), last_output: None, pending: vec!() });
+        self.nodes.len() - 1
+    }
+
+    pub fn send(&mut self, node: NodeId, value: i64) {
+        self.queues[node].push_back(value);
+    }
+
+    // Each machine feeds the next; the last one's outputs are collected.
+    pub fn chain(vms: Vec<Vm>) -> Network {
+        let count = vms.len();
+        let mut network = Network::new(Schedule::EventDriven);
+        for (i, vm) in vms.into_iter().enumerate() {
+            let route = if i + 1 < count { Route::To(vec!(i + 1)) } else { Route::Collect };
+            network.add(vm, route);
+        }
+        network
+    }
+
+    // Like a chain, but the last machine feeds the first.
+    pub fn ring(vms: Vec<Vm>) -> Network {
+        let count = vms.len();
+        let mut network = Network::new(Schedule::EventDriven);
+        for (i, vm) in vms.into_iter().enumerate() {
+            network.add(vm, Route::To(vec!((i + 1) % count)));
+        }
+        network
+    }
+
+    // The hub (node 0) broadcasts to every spoke, and every spoke reports back to the hub.
+    pub fn star(hub: Vm, spokes: Vec<Vm>) -> Network {
+        let mut network = Network::new(Schedule::EventDriven);
+        network.add(hub, Route::To((1..=spokes.len()).collect()));
+        for vm in spokes {
+            network.add(vm, Route::To(vec!(0)));
+        }
+        network
+    }
+
+    // Machines exchanging addressed packets. Each is sent its own address first, and reads
+    // `idle_input` whenever it has nothing waiting.
+    pub fn bus(vms: Vec<Vm>, width: usize, idle_input: i64) -> Network {
+        assert!(width > 0, "packets need room for at least an address");
+        let mut network = Network::new(Schedule::RoundRobin { slice: 1000 });
+        for vm in vms {
+            let node = network.add(vm, Route::Packets { width });
+            network.nodes[node].idle_input = Some(idle_input);
+            network.send(node, node as i64);
+        }
+        network
+    }
+
+    fn turn(&mut self, id: NodeId) -> Result<(Turn, bool), NetworkError> {
+        let Node { vm, route, idle_input, collected, last_output, pending } = &mut self.nodes[id];
+        if vm.halted() {
+            return Ok((Turn::Halted, false));
+        }
+
+        let mut io = NodeIo {
+            queue: id,
+            queues: &mut self.queues,
+            route,
+            pending,
+            collected,
+            last_output,
+            unrouted: &mut self.unrouted,
+            idle_input: *idle_input,
+            moved: false,
+            polled_empty: false
+        };
+        let slice = match self.schedule {
+            Schedule::RoundRobin { slice } => slice,
+            Schedule::EventDriven => usize::MAX
+        };
+
+        let mut turn = Turn::Preempted;
+        for _ in 0..slice {
+            if !vm.step_with(&mut io).map_err(|error| NetworkError::Machine { node: id, error })? {
+                turn = if vm.halted() { Turn::Halted } else { Turn::Blocked };
+                break;
+            }
+            if io.polled_empty {
+                turn = Turn::Polled;
+                break;
+            }
+        }
+        Ok((turn, io.moved))
+    }
+
+    // Runs the machines until they have all halted, deadlocked or gone idle.
+    pub fn run(&mut self) -> Result<Outcome, NetworkError> {
+        for (node, Node { route, .. }) in self.nodes.iter().enumerate() {
+            if let Route::To(targets) = route {
+                if let Some(target) = targets.iter().find(|target| **target >= self.queues.len()) {
+                    return Err(NetworkError::NoSuchNode { node, target: *target });
+                }
+            }
+        }
+
+        loop {
+            let mut moved = false;
+            let mut waiting = true;
+            let mut polled = false;
+            let mut live = false;
+
+            for id in 0..self.nodes.len() {
+                let (turn, node_moved) = self.turn(id)?;
+                moved |= node_moved;
+                match turn {
+                    Turn::Halted => {},
+                    Turn::Blocked => live = true,
+                    Turn::Polled => {
+                        live = true;
+                        polled = true;
+                    },
+                    Turn::Preempted => {
+                        live = true;
+                        waiting = false;
+                    }
+                }
+            }
+
+            if !live {
+                return Ok(Outcome::Halted);
+            }
+            if !moved && waiting {
+                return Ok(if polled { Outcome::Idle } else { Outcome::Deadlock });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    const AMPLIFIER: [i64; 17] = [3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0];
+    const FEEDBACK: [i64; 29] = [3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5];
+
+    fn amplifiers(program: &[i64], phases: &[i64]) -> Vec<Vm> {
+        phases.iter().map(|phase| Vm::with_inputs(program.to_vec(), vec!(*phase))).collect()
+    }
+
+    #[test]
+    fn test_chain() {
+        let mut network = Network::chain(amplifiers(&AMPLIFIER, &[4, 3, 2, 1, 0]));
+        network.send(0, 0);
+        assert_eq!(network.run().unwrap(), Outcome::Halted);
+        assert_eq!(network.nodes[4].collected, vec!(43210));
+    }
+
+    #[test]
+    fn test_ring() {
+        for schedule in [Schedule::EventDriven, Schedule::RoundRobin { slice: 3 }].iter() {
+            let mut network = Network::ring(amplifiers(&FEEDBACK, &[9, 8, 7, 6, 5]));
+            network.schedule = *schedule;
+            network.send(0, 0);
+            assert_eq!(network.run().unwrap(), Outcome::Halted);
+            assert_eq!(network.nodes[4].last_output, Some(139629729));
+            assert_eq!(network.queues[0], vec!(139629729));
+        }
+    }
+
+    #[test]
+    fn test_star() {
+        // the hub sends its input to both spokes, which double it; the hub outputs the sum
+        let hub = assemble("IN [20]\nOUT [20]\nIN [21]\nIN [22]\nADD [21], [22], [23]\nOUT [23]\nHLT").unwrap();
+        let spoke = assemble("IN [20]\nMUL [20], #2, [20]\nOUT [20]\nHLT").unwrap();
+        let mut network = Network::star(Vm::new(hub), vec!(Vm::new(spoke.clone()), Vm::new(spoke)));
+        network.send(0, 5);
+        assert_eq!(network.run().unwrap(), Outcome::Halted);
+        assert_eq!(network.nodes[0].last_output, Some(20));
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let waiter = vec!(3,5,4,5,99,0);
+        let mut network = Network::ring(vec!(Vm::new(waiter.clone()), Vm::new(waiter)));
+        assert_eq!(network.run().unwrap(), Outcome::Deadlock);
+        network.send(1, 7);
+        assert_eq!(network.run().unwrap(), Outcome::Halted);
+        assert_eq!(network.queues[1], vec!(7));
+    }
+
+    #[test]
+    fn test_bus_goes_idle() {
+        // sends [address + 1, address * 10], then forwards anything it receives to address 255
+        let node = assemble("
+                IN [addr]
+                ADD [addr], #1, [dest]
+                OUT [dest]
+                MUL [addr], #10, [val]
+                OUT [val]
+            poll:
+                IN [x]
+                EQ [x], #-1, [t]
+                JNZ [t], #poll
+                OUT #255
+                OUT [x]
+                JNZ #1, #poll
+            addr: db 0
+            dest: db 0
+            val: db 0
+            x: db 0
+            t: db 0
+        ").unwrap();
+
+        let mut network = Network::bus(vec!(Vm::new(node.clone()), Vm::new(node)), 2, -1);
+        assert_eq!(network.run().unwrap(), Outcome::Idle);
+        assert_eq!(network.unrouted, vec!(vec!(2, 10), vec!(255, 0)));
+
+        network.send(0, 3);
+        assert_eq!(network.run().unwrap(), Outcome::Idle);
+        assert_eq!(network.unrouted[2], vec!(255, 3));
+    }
+
+    #[test]
+    #[should_panic(expected = "packets need room for at least an address")]
+    fn test_empty_packets() {
+        Network::bus(vec!(Vm::new(vec!(104,0,99))), 0, -1);
+    }
+
+    #[test]
+    fn test_errors_name_the_node() {
+        let mut network = Network::chain(vec!(Vm::new(vec!(3,0,4,0,99)), Vm::new(vec!(3,0,42))));
+        network.send(0, 1);
+        let error = network.run().unwrap_err();
+        assert!(matches!(error, NetworkError::Machine { node: 1, error: VmError::UnknownOp { fault } } if fault.ip == 2));
+        assert!(error.to_string().starts_with("node 1: no instruction for op 42"));
+    }
+
+    #[test]
+    fn test_routes_to_missing_nodes() {
+        let mut network = Network::new(Schedule::EventDriven);
+        network.add(Vm::new(vec!(104,1,99)), Route::To(vec!(1)));
+        network.add(Vm::new(vec!(104,2,99)), Route::To(vec!(0, 3)));
+        let error = network.run().unwrap_err();
+        assert_eq!(error, NetworkError::NoSuchNode { node: 1, target: 3 });
+        assert_eq!(error.to_string(), "node 1 routes to missing node 3");
+        assert!(!network.nodes[0].vm.halted());
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::fmt::Write;
+
+use crate::{mnemonic, Instruction, Mode, OP_HALT, OP_JNZ, OP_JZ};
+
+const MODE_NAMES: [&str; 3] = ["position", "immediate", "relative"];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IpHits {
+    pub count: u64,
+    pub op: i64,
+    pub size: i64
+}
+
+// A straight run of instructions that is only ever entered at its start.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: i64,
+    pub end: i64,
+    pub instructions: usize,
+    pub entries: u64,
+    pub executed: u64
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub total: u64,
+    pub ips: HashMap<i64, IpHits>,
+    pub ops: BTreeMap<i64, u64>,
+    pub modes: [u64; 3],
+    // Addresses control arrived at other than by falling through, plus the first ip run.
+    pub leaders: BTreeSet<i64>
+}
+
+fn mode_index(mode: Mode) -> usize {
+    match mode {
+        Mode::Normal => 0,
+        Mode::Imm => 1,
+        Mode::Base => 2
+    }
+}
+
+fn ends_block(op: i64) -> bool {
+    op == OP_JNZ || op == OP_JZ || op == OP_HALT
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    // Profiling is switched on for the day binaries by setting INTCODE_PROFILE.
+    pub fn from_env() -> Option<Profile> {
+        env::var_os("INTCODE_PROFILE").map(|_| Profile::new())
+    }
+
+    pub fn count(&mut self, ip: i64, instruction: &Instruction, next_ip: i64) {
+        let op = instruction.op();
+        let size = instruction.size();
+
+        if self.total == 0 {
+            self.leaders.insert(ip);
+        }
+        self.total += 1;
+
+        let hits = self.ips.entry(ip).or_insert(IpHits { count: 0, op, size });
+        hits.count += 1;
+        hits.op = op;
+        hits.size = size;
+
+        *self.ops.entry(op).or_insert(0) += 1;
+        for arg in instruction.args() {
+            self.modes[mode_index(arg.mode)] += 1;
+        }
+
+        if next_ip != ip + size {
+            self.leaders.insert(next_ip);
+        }
+        if ends_block(op) {
+            self.leaders.insert(ip + size);
+        }
+    }
+
+    // Folds in the counts from another run, e.g. one of many fresh VMs on the same program.
+    pub fn merge(&mut self, other: &Profile) {
+        self.total += other.total;
+        for (ip, hits) in &other.ips {
+            let entry = self.ips.entry(*ip).or_insert(IpHits { count: 0, ..*hits });
+            entry.count += hits.count;
+        }
+        for (op, count) in &other.ops {
+            *self.ops.entry(*op).or_insert(0) += count;
+        }
+        for (mine, theirs) in self.modes.iter_mut().zip(other.modes.iter()) {
+            *mine += theirs;
+        }
+        self.leaders.extend(other.leaders.iter());
+    }
+
+    // Basic blocks ordered by the number of instructions executed inside them, hottest first.
+    pub fn blocks(&self) -> Vec<Block> {
+        let mut blocks = vec!();
+
+        for leader in &self.leaders {
+            let entries = match self.ips.get(leader) {
+                Some(hits) => hits.count,
+                None => continue
+            };
+            let mut block = Block { start: *leader, end: *leader, instructions: 0, entries, executed: 0 };
+            let mut ip = *leader;
+            while let Some(hits) = self.ips.get(&ip) {
+                block.end = ip;
+                block.instructions += 1;
+                block.executed += hits.count;
+                ip += hits.size;
+                if ends_block(hits.op) || self.leaders.contains(&ip) {
+                    break;
+                }
+            }
+            blocks.push(block);
+        }
+
+        blocks.sort_by(|a, b| b.executed.cmp(&a.executed).then(a.start.cmp(&b.start)));
+        blocks
+    }
+
+    pub fn report(&self, top_blocks: usize) -> String {
+        let percent = |n: u64| if self.total == 0 { 0.0 } else { n as f64 * 100.0 / self.total as f64 };
+        let mut result = String::new();
+
+        writeln!(result, "instructions executed: {}", self.total).unwrap();
+        writeln!(result, "by opcode:").unwrap();
+        for (op, count) in &self.ops {
+            let name = mnemonic(*op).unwrap_or("?");
+            writeln!(result, "  {:<4} {:>12} {:>6.2}%", name, count, percent(*count)).unwrap();
+        }
+
+        let operands: u64 = self.modes.iter().sum();
+        writeln!(result, "by operand mode:").unwrap();
+        for (name, count) in MODE_NAMES.iter().zip(self.modes.iter()) {
+            let share = if operands == 0 { 0.0 } else { *count as f64 * 100.0 / operands as f64 };
+            writeln!(result, "  {:<9} {:>12} {:>6.2}%", name, count, share).unwrap();
+        }
+
+        writeln!(result, "hottest blocks:").unwrap();
+        for block in self.blocks().iter().take(top_blocks) {
+            writeln!(result, "  {:>5}-{:<5} {:>3} instrs {:>10} entries {:>12} executed {:>6.2}%",
+                     block.start, block.end, block.instructions, block.entries, block.executed,
+                     percent(block.executed)).unwrap();
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vm;
+
+    // count [20] down from 3, outputting each value
+    const COUNTDOWN: [i64; 14] = [1101,3,0,20,4,20,1001,20,-1,20,1005,20,4,99];
+
+    fn profiled(program: Vec<i64>) -> Profile {
+        let mut vm = Vm::new(program);
+        vm.profile = Some(Profile::new());
+        vm.run().unwrap();
+        vm.profile.unwrap()
+    }
+
+    #[test]
+    fn test_counts() {
+        let profile = profiled(COUNTDOWN.to_vec());
+
+        assert_eq!(profile.total, 1 + 3 * 3 + 1);
+        assert_eq!(profile.ips[&0].count, 1);
+        assert_eq!(profile.ips[&13].count, 1);
+        assert_eq!(profile.ips[&4].count, 3);
+        assert_eq!(profile.ops[&OP_JNZ], 3);
+        assert_eq!(profile.modes, [13, 8, 0]);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let blocks = profiled(COUNTDOWN.to_vec()).blocks();
+
+        assert_eq!(blocks, vec!(
+            Block { start: 4, end: 10, instructions: 3, entries: 3, executed: 9 },
+            Block { start: 0, end: 0, instructions: 1, entries: 1, executed: 1 },
+            Block { start: 13, end: 13, instructions: 1, entries: 1, executed: 1 }
+        ));
+    }
+
+    #[test]
+    fn test_merge_and_report() {
+        let mut profile = profiled(COUNTDOWN.to_vec());
+        profile.merge(&profiled(COUNTDOWN.to_vec()));
+
+        assert_eq!(profile.total, 22);
+        assert_eq!(profile.blocks()[0].entries, 6);
+
+        let report = profile.report(1);
+        assert!(report.starts_with("instructions executed: 22\n"));
+        assert!(report.contains("  JNZ             6  27.27%\n"));
+        assert!(report.ends_with("hottest blocks:\n      4-10      3 instrs          6 entries           18 executed  81.82%\n"));
+    }
+}
@@ -0,0 +1,171 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::compile::Compiled;
+use crate::network::{Network, NetworkError, Outcome};
+use crate::Vm;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Topology {
+    // Each amplifier feeds the next and the last one's first output is the signal.
+    Serial,
+    // The last amplifier feeds back into the first; the signal is its final output.
+    Feedback
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Best {
+    pub phases: Vec<i64>,
+    pub signal: i64
+}
+
+// Tries every ordering of the phase values on a program, one amplifier per phase, and keeps
+// the ordering with the highest signal.
+pub struct Search {
+    pub program: Vec<i64>,
+    pub phases: Vec<i64>,
+    pub topology: Topology,
+    pub threads: usize,
+    // Don't print the signal of every ordering as it's evaluated.
+    pub quiet: bool
+}
+
+// Every ordering of the values, by Heap's algorithm.
+pub fn permutations(values: &[i64]) -> Vec<Vec<i64>> {
+    let mut values = values.to_vec();
+    let mut counters = vec![0; values.len()];
+    let mut result = vec!(values.clone());
+    let mut i = 1;
+    while i < values.len() {
+        if counters[i] < i {
+            let swap = if i % 2 == 0 { 0 } else { counters[i] };
+            values.swap(swap, i);
+            result.push(values.clone());
+            counters[i] += 1;
+            i = 1;
+        } else {
+            counters[i] = 0;
+            i += 1;
+        }
+    }
+    result
+}
+
+impl Search {
+    pub fn new(program: Vec<i64>, phases: Vec<i64>, topology: Topology) -> Search {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Search { program, phases, topology, threads, quiet: false }
+    }
+
+    // The signal for one ordering, or None if the amplifiers never produce one.
+    pub fn signal(&self, phases: &[i64]) -> Result<Option<i64>, NetworkError> {
+        let mut vm = Vm::new(self.program.clone());
+        let compiled = vm.compile();
+        self.evaluate(&compiled, phases)
+    }
+
+    fn evaluate(&self, compiled: &Arc<Compiled>, phases: &[i64]) -> Result<Option<i64>, NetworkError> {
+        let vms = phases.iter().map(|phase| {
+            let mut vm = Vm::with_inputs(self.program.clone(), vec!(*phase));
+            vm.use_compiled(compiled.clone());
+            vm
+        }).collect();
+
+        let mut network = match self.topology {
+            Topology::Serial => Network::chain(vms),
+            Topology::Feedback => Network::ring(vms)
+        };
+        network.send(0, 0);
+        if network.run()? != Outcome::Halted {
+            return Ok(None);
+        }
+        let last = network.nodes.last().expect("at least one amplifier");
+        Ok(match self.topology {
+            Topology::Serial => last.collected.first().copied(),
+            Topology::Feedback => last.last_output
+        })
+    }
+
+    // Ties go to the ordering that comes first in `permutations`, however the work was split.
+    pub fn run(&self) -> Result<Option<Best>, NetworkError> {
+        if self.phases.is_empty() {
+            return Ok(None);
+        }
+        let orderings = permutations(&self.phases);
+        let compiled = Vm::new(self.program.clone()).compile();
+        let next = AtomicUsize::new(0);
+        let signals = Mutex::new(vec![Ok(None); orderings.len()]);
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads.clamp(1, orderings.len()) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let phases = match orderings.get(index) {
+                        Some(phases) => phases,
+                        None => break
+                    };
+                    let signal = self.evaluate(&compiled, phases);
+                    if !self.quiet {
+                        println!("{:?} -> {:?}", phases, signal);
+                    }
+                    signals.lock().unwrap()[index] = signal;
+                });
+            }
+        });
+
+        let mut best: Option<Best> = None;
+        for (phases, signal) in orderings.into_iter().zip(signals.into_inner().unwrap()) {
+            if let Some(signal) = signal? {
+                if best.as_ref().is_none_or(|best| signal > best.signal) {
+                    best = Some(Best { phases, signal });
+                }
+            }
+        }
+        Ok(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    const AMPLIFIER: [i64; 17] = [3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0];
+    const FEEDBACK: [i64; 29] = [3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5];
+
+    #[test]
+    fn test_permutations() {
+        let all = permutations(&[0, 1, 2, 3]);
+        assert_eq!(all.len(), 24);
+        assert_eq!(all.iter().collect::<BTreeSet<_>>().len(), 24);
+        assert_eq!(permutations(&[]), vec!(vec!()));
+    }
+
+    #[test]
+    fn test_serial() {
+        let search = Search::new(AMPLIFIER.to_vec(), (0..5).collect(), Topology::Serial);
+        assert_eq!(search.signal(&[4, 3, 2, 1, 0]).unwrap(), Some(43210));
+        assert_eq!(search.run().unwrap(), Some(Best { phases: vec!(4, 3, 2, 1, 0), signal: 43210 }));
+    }
+
+    #[test]
+    fn test_feedback_on_any_thread_count() {
+        for threads in 1..4 {
+            let mut search = Search::new(FEEDBACK.to_vec(), (5..10).collect(), Topology::Feedback);
+            search.threads = threads;
+            search.quiet = true;
+            assert_eq!(search.run().unwrap(), Some(Best { phases: vec!(9, 8, 7, 6, 5), signal: 139629729 }));
+        }
+    }
+
+    #[test]
+    fn test_errors_and_no_signal() {
+        let search = Search::new(vec!(42), vec!(0, 1), Topology::Serial);
+        assert!(search.run().is_err());
+
+        // reads its phase and then waits forever for a signal that never comes
+        let search = Search::new(vec!(3,7,3,7,99,0,0,0), vec!(0, 1), Topology::Feedback);
+        assert_eq!(search.run().unwrap(), None);
+    }
+}
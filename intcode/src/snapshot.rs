@@ -0,0 +1,303 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::{memory, Arithmetic, Memory, Vm};
+
+const HEADER: &str = "intcode-snapshot 2";
+
+// Everything needed to carry on running a machine later, or somewhere else.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub ip: i64,
+    pub base: i64,
+    pub arithmetic: Arithmetic,
+    pub memory: Memory,
+    pub inputs: VecDeque<i64>,
+    pub outputs: Vec<i64>
+}
+
+impl Vm {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            ip: self.ip,
+            base: self.base,
+            arithmetic: self.arithmetic,
+            memory: self.memory.clone(),
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone()
+        }
+    }
+
+    // Puts the machine back into the saved state, reusing its buffers where it can.
+    // The debug level, tracer and profile are left alone. The decode cache only loses entries
+    // for memory that actually changes, and compiled code is dropped if its code changes.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.ip = snapshot.ip;
+        self.base = snapshot.base;
+        self.arithmetic = snapshot.arithmetic;
+        if let Some(cache) = &mut self.cache {
+            for (start, words) in self.memory.pages().chain(snapshot.memory.pages()) {
+                for address in start..start + words.len() as i64 {
+                    if self.memory.get(address) != snapshot.memory.get(address) {
+                        cache.invalidate(address);
+                    }
+                }
+            }
+        }
+        if self.compiled.as_ref().is_some_and(|compiled| !compiled.matches(&snapshot.memory)) {
+            self.compiled = None;
+        }
+        self.memory.clone_from(&snapshot.memory);
+        self.inputs.clone_from(&snapshot.inputs);
+        self.outputs.clone_from(&snapshot.outputs);
+    }
+
+    pub fn from_snapshot(snapshot: &Snapshot) -> Vm {
+        let mut vm = Vm::new(vec!());
+        vm.restore(snapshot);
+        vm
+    }
+}
+
+fn join(values: impl Iterator<Item = i64>) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<String>>().join(",")
+}
+
+fn parse_list(text: &str) -> Result<Vec<i64>, String> {
+    if text.is_empty() {
+        return Ok(vec!());
+    }
+    text.split(',')
+        .map(|tok| tok.trim().parse::<i64>().map_err(|_| format!("bad number {}", tok)))
+        .collect()
+}
+
+fn arithmetic_name(arithmetic: Arithmetic) -> &'static str {
+    match arithmetic {
+        Arithmetic::Wrap => "wrap",
+        Arithmetic::Trap => "trap",
+        Arithmetic::Promote => "promote"
+    }
+}
+
+// Pages are written one per line with their trailing zeros dropped; untouched pages are skipped.
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "ip {}", self.ip)?;
+        writeln!(f, "base {}", self.base)?;
+        writeln!(f, "arithmetic {}", arithmetic_name(self.arithmetic))?;
+        writeln!(f, "limit {}", self.memory.limit())?;
+        for (start, words) in self.memory.pages() {
+            let used = words.iter().rposition(|w| *w != 0).map_or(0, |i| i + 1);
+            writeln!(f, "page {} {}", start, join(words[..used].iter().copied()))?;
+        }
+        writeln!(f, "inputs {}", join(self.inputs.iter().copied()))?;
+        writeln!(f, "outputs {}", join(self.outputs.iter().copied()))
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Snapshot, String> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("not an intcode snapshot".to_string());
+        }
+
+        let mut fields = HashMap::new();
+        let mut pages = vec!();
+        for line in lines {
+            let (key, value) = match line.find(' ') {
+                Some(i) => (&line[..i], line[i + 1..].trim()),
+                None => (line, "")
+            };
+            if key == "page" {
+                pages.push(value);
+            } else {
+                fields.insert(key, value);
+            }
+        }
+        let field = |key: &str| fields.get(key).copied().ok_or(format!("missing {}", key));
+        let number = |key: &str| field(key)?.parse::<i64>().map_err(|_| format!("bad {}", key));
+
+        let ip = number("ip")?;
+        let base = number("base")?;
+        let arithmetic = match field("arithmetic")? {
+            "wrap" => Arithmetic::Wrap,
+            "trap" => Arithmetic::Trap,
+            "promote" => Arithmetic::Promote,
+            name => return Err(format!("bad arithmetic {}", name))
+        };
+        let limit = number("limit")?;
+        if limit < 0 {
+            return Err("bad limit".to_string());
+        }
+        // the limit is only a guard, so a file mustn't be able to raise it past the default
+        if limit as u64 > memory::DEFAULT_LIMIT as u64 {
+            return Err(format!("limit {} is over {}", limit, memory::DEFAULT_LIMIT));
+        }
+
+        let mut memory = Memory::with_limit(limit as usize);
+        for page in pages {
+            let (start, words) = match page.find(' ') {
+                Some(i) => (&page[..i], &page[i + 1..]),
+                None => (page, "")
+            };
+            let start = start.parse::<i64>().map_err(|_| format!("bad page {}", start))?;
+            for (offset, word) in parse_list(words)?.into_iter().enumerate() {
+                let address = start.checked_add(offset as i64).ok_or(format!("page {} is out of range", start))?;
+                memory.set(address, word).map_err(|_| format!("page {} is out of range", start))?;
+            }
+        }
+
+        Ok(Snapshot {
+            ip,
+            base,
+            arithmetic,
+            memory,
+            inputs: parse_list(field("inputs")?)?.into(),
+            outputs: parse_list(field("outputs")?)?
+        })
+    }
+}
+
+impl Snapshot {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn load(path: &Path) -> io::Result<Snapshot> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // outputs input + 1 via memory past the program, forever
+    const INCREMENT: [i64; 11] = [203,100,22101,1,100,101,204,101,1105,1,0];
+
+    #[test]
+    fn test_restore_rewinds() {
+        let mut vm = Vm::with_inputs(INCREMENT.to_vec(), vec!(1));
+        vm.base = 5;
+        vm.run().unwrap();
+        let saved = vm.snapshot();
+        assert_eq!(saved.outputs, vec!(2));
+
+        vm.push_input(10);
+        vm.run().unwrap();
+        assert_eq!(vm.outputs, vec!(2, 11));
+        assert_eq!(vm.peek(105), 10);
+
+        vm.restore(&saved);
+        assert_eq!(vm.outputs, vec!(2));
+        assert_eq!(vm.peek(105), 1);
+
+        vm.push_input(20);
+        vm.run().unwrap();
+        assert_eq!(vm.outputs, vec!(2, 21));
+    }
+
+    #[test]
+    fn test_fork() {
+        let mut vm = Vm::new(INCREMENT.to_vec());
+        vm.base = 5;
+        let fork_point = vm.snapshot();
+
+        let results: Vec<i64> = (0..3).map(|input| {
+            let mut fork = Vm::from_snapshot(&fork_point);
+            fork.push_input(input);
+            fork.run().unwrap();
+            fork.outputs[0]
+        }).collect();
+        assert_eq!(results, vec!(1, 2, 3));
+        assert!(vm.outputs.is_empty());
+    }
+
+    #[test]
+    fn test_restore_with_cache() {
+        // outputs [9], then overwrites its own OUT with a halt
+        let mut vm = Vm::new(vec!(4,9,1101,0,99,2,99,0,0,42));
+        vm.cache = Some(crate::cache::DecodeCache::new());
+        let fresh = vm.snapshot();
+
+        for _ in 0..3 {
+            vm.restore(&fresh);
+            vm.run().unwrap();
+            assert_eq!(vm.outputs, vec!(42));
+            assert_eq!(vm.peek(2), 99);
+        }
+        let cache = vm.cache.unwrap();
+        assert_eq!(cache.hits, 4);
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut vm = Vm::with_inputs(INCREMENT.to_vec(), vec!(1, 2));
+        vm.base = -5;
+        vm.write(3072, -7).unwrap();
+        vm.run_until_event().unwrap();
+
+        let snapshot = vm.snapshot();
+        let text = snapshot.to_string();
+        // the input lands at 95 and its increment at 96, after a run of zeros
+        let page0 = format!("203,100,22101,1,100,101,204,101,1105,1,0{},1,2", ",0".repeat(84));
+        assert_eq!(text, format!("intcode-snapshot 2\nip 8\nbase -5\narithmetic wrap\nlimit 16777216\npage 0 {}\npage 3072 -7\ninputs 2\noutputs \n", page0));
+        assert_eq!(text.parse::<Snapshot>().unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_round_trip_keeps_policy_and_zero_pages() {
+        for arithmetic in [Arithmetic::Wrap, Arithmetic::Trap, Arithmetic::Promote].iter() {
+            let mut vm = Vm::new(INCREMENT.to_vec());
+            vm.arithmetic = *arithmetic;
+            // allocates a page that's still all zeros
+            vm.write(5000, 0).unwrap();
+
+            let snapshot = vm.snapshot();
+            let loaded = snapshot.to_string().parse::<Snapshot>().unwrap();
+            assert_eq!(loaded, snapshot);
+            assert_eq!(Vm::from_snapshot(&loaded).arithmetic, *arithmetic);
+        }
+    }
+
+    #[test]
+    fn test_bad_snapshots() {
+        assert!("hello".parse::<Snapshot>().is_err());
+        assert_eq!("intcode-snapshot 2\nip 0\n".parse::<Snapshot>().unwrap_err(), "missing base");
+        let bad = "intcode-snapshot 2\nip 0\nbase 0\narithmetic wrap\nlimit 100\npage 0 1,x\ninputs \noutputs \n";
+        assert_eq!(bad.parse::<Snapshot>().unwrap_err(), "bad number x");
+        let bad = "intcode-snapshot 2\nip 0\nbase 0\narithmetic wrap\nlimit 100\npage 100 1\ninputs \noutputs \n";
+        assert_eq!(bad.parse::<Snapshot>().unwrap_err(), "page 100 is out of range");
+        let bad = "intcode-snapshot 2\nip 0\nbase 0\narithmetic saturate\nlimit 100\ninputs \noutputs \n";
+        assert_eq!(bad.parse::<Snapshot>().unwrap_err(), "bad arithmetic saturate");
+        let bad = "intcode-snapshot 2\nip 0\nbase 0\narithmetic wrap\nlimit 9223372036854775807\npage 9223372036854775000 1\ninputs \noutputs \n";
+        assert_eq!(bad.parse::<Snapshot>().unwrap_err(), "limit 9223372036854775807 is over 16777216");
+        let bad = "intcode-snapshot 2\nip 0\nbase 0\narithmetic wrap\nlimit 100\npage 9223372036854775807 1,2\ninputs \noutputs \n";
+        assert_eq!(bad.parse::<Snapshot>().unwrap_err(), "page 9223372036854775807 is out of range");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("intcode-snapshot-{}", std::process::id()));
+        let mut vm = Vm::with_inputs(INCREMENT.to_vec(), vec!(4));
+        vm.base = 5;
+        vm.run().unwrap();
+
+        vm.snapshot().save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, vm.snapshot());
+    }
+}
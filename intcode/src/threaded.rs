@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::{IoDevice, Vm, VmError};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Exit {
+    Halted,
+    // Nothing arrived on the input channel within the timeout.
+    TimedOut,
+    // Every sender for the input channel has gone away.
+    InputClosed
+}
+
+// A machine handed back by its thread, still waiting on input unless it halted.
+pub struct Finished {
+    pub vm: Vm,
+    pub exit: Exit,
+    pub last_output: Option<i64>
+}
+
+pub struct ChannelIo {
+    pub input: Receiver<i64>,
+    pub output: Sender<i64>,
+    // How long to block waiting for input; None waits forever.
+    pub timeout: Option<Duration>,
+    exit: Option<Exit>,
+    last_output: Option<i64>
+}
+
+impl ChannelIo {
+    pub fn new(input: Receiver<i64>, output: Sender<i64>, timeout: Option<Duration>) -> ChannelIo {
+        ChannelIo { input, output, timeout, exit: None, last_output: None }
+    }
+}
+
+impl IoDevice for ChannelIo {
+    fn input(&mut self) -> Option<i64> {
+        let received = match self.timeout {
+            Some(timeout) => self.input.recv_timeout(timeout),
+            None => self.input.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+        match received {
+            Ok(v) => Some(v),
+            Err(RecvTimeoutError::Timeout) => {
+                self.exit = Some(Exit::TimedOut);
+                None
+            },
+            Err(RecvTimeoutError::Disconnected) => {
+                self.exit = Some(Exit::InputClosed);
+                None
+            }
+        }
+    }
+
+    fn output(&mut self, v: i64) {
+        self.last_output = Some(v);
+        // nobody listening any more is fine; the value just goes nowhere
+        let _ = self.output.send(v);
+    }
+}
+
+// Runs the machine on its own thread until it halts or its input dries up.
+pub fn spawn(mut vm: Vm, mut io: ChannelIo) -> JoinHandle<Result<Finished, VmError>> {
+    thread::spawn(move || {
+        // anything already pushed to the machine is read before the channel
+        let mut queued = mem::take(&mut vm.inputs);
+        vm.run_with(&mut Prefixed { queued: &mut queued, io: &mut io })?;
+        vm.inputs = queued;
+        let exit = if vm.halted() { Exit::Halted } else { io.exit.unwrap_or(Exit::InputClosed) };
+        Ok(Finished { vm, exit, last_output: io.last_output })
+    })
+}
+
+struct Prefixed<'a> {
+    queued: &'a mut VecDeque<i64>,
+    io: &'a mut ChannelIo
+}
+
+impl<'a> IoDevice for Prefixed<'a> {
+    fn input(&mut self) -> Option<i64> {
+        self.queued.pop_front().or_else(|| self.io.input())
+    }
+
+    fn output(&mut self, v: i64) {
+        self.io.output(v)
+    }
+}
+
+// Waits for every machine, even once one has failed, then reports the first failure.
+fn join(handles: Vec<JoinHandle<Result<Finished, VmError>>>) -> Result<Vec<Finished>, VmError> {
+    let results: Vec<Result<Finished, VmError>> = handles.into_iter()
+        .map(|handle| handle.join().expect("vm thread panicked"))
+        .collect();
+    results.into_iter().collect()
+}
+
+// Each machine on its own thread, feeding the next; `first` is sent to the first machine and
+// the last one's outputs come back on the returned receiver.
+pub fn chain(vms: Vec<Vm>, first: &[i64], timeout: Option<Duration>) -> Result<(Vec<Finished>, Receiver<i64>), VmError> {
+    let (sender, mut receiver) = channel();
+    for v in first {
+        sender.send(*v).expect("receiver is alive");
+    }
+    drop(sender);
+
+    let mut handles = vec!();
+    for vm in vms {
+        let (next_sender, next_receiver) = channel();
+        handles.push(spawn(vm, ChannelIo::new(receiver, next_sender, timeout)));
+        receiver = next_receiver;
+    }
+    Ok((join(handles)?, receiver))
+}
+
+// Like a chain, but the last machine feeds the first.
+pub fn ring(vms: Vec<Vm>, first: &[i64], timeout: Option<Duration>) -> Result<Vec<Finished>, VmError> {
+    let count = vms.len();
+    let (senders, receivers): (Vec<Sender<i64>>, Vec<Receiver<i64>>) = (0..count).map(|_| channel()).unzip();
+    for v in first {
+        senders[0].send(*v).expect("receiver is alive");
+    }
+
+    let handles = vms.into_iter().zip(receivers).enumerate()
+        .map(|(i, (vm, receiver))| spawn(vm, ChannelIo::new(receiver, senders[(i + 1) % count].clone(), timeout)))
+        .collect();
+    drop(senders);
+    join(handles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AMPLIFIER: [i64; 17] = [3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0];
+    const FEEDBACK: [i64; 29] = [3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5];
+
+    fn amplifiers(program: &[i64], phases: &[i64]) -> Vec<Vm> {
+        phases.iter().map(|phase| Vm::with_inputs(program.to_vec(), vec!(*phase))).collect()
+    }
+
+    #[test]
+    fn test_chain() {
+        let (finished, output) = chain(amplifiers(&AMPLIFIER, &[4, 3, 2, 1, 0]), &[0], None).unwrap();
+        assert!(finished.iter().all(|f| f.exit == Exit::Halted));
+        assert_eq!(output.recv().unwrap(), 43210);
+    }
+
+    #[test]
+    fn test_ring() {
+        let finished = ring(amplifiers(&FEEDBACK, &[9, 8, 7, 6, 5]), &[0], None).unwrap();
+        assert_eq!(finished[4].last_output, Some(139629729));
+        assert!(finished.iter().all(|f| f.exit == Exit::Halted));
+    }
+
+    #[test]
+    fn test_timeout() {
+        // waits for two inputs but only ever gets one
+        let (sender, receiver) = channel();
+        let (output, outputs) = channel();
+        sender.send(4).unwrap();
+        let handle = spawn(Vm::new(vec!(3,9,4,9,3,9,4,9,99,0)), ChannelIo::new(receiver, output, Some(Duration::from_millis(20))));
+
+        let finished = handle.join().unwrap().unwrap();
+        assert_eq!(finished.exit, Exit::TimedOut);
+        assert!(finished.vm.needs_input());
+        assert_eq!(outputs.try_iter().collect::<Vec<i64>>(), vec!(4));
+        drop(sender);
+    }
+
+    #[test]
+    fn test_closed_input_and_errors() {
+        let (sender, receiver) = channel();
+        let (output, _) = channel();
+        drop(sender);
+        let finished = spawn(Vm::new(vec!(3,0,99)), ChannelIo::new(receiver, output.clone(), None)).join().unwrap().unwrap();
+        assert_eq!(finished.exit, Exit::InputClosed);
+
+        let (_sender, receiver) = channel();
+        let error = spawn(Vm::new(vec!(42)), ChannelIo::new(receiver, output, None)).join().unwrap();
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn test_join_waits_for_every_machine() {
+        let (_sender, receiver) = channel();
+        let (output, outputs) = channel();
+        let failing = spawn(Vm::new(vec!(42)), ChannelIo::new(receiver, output.clone(), None));
+
+        // echoes one input, which only turns up a while after the other machine has failed
+        let (slow_sender, slow_receiver) = channel();
+        let slow = spawn(Vm::new(vec!(3,5,4,5,99,0)), ChannelIo::new(slow_receiver, output, None));
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            slow_sender.send(7).unwrap();
+        });
+
+        assert!(join(vec!(failing, slow)).is_err());
+        assert_eq!(outputs.try_iter().collect::<Vec<i64>>(), vec!(7));
+    }
+}
@@ -0,0 +1,277 @@
+use std::fmt::Write as _;
+use std::io::{self, BufWriter, Read, Write};
+
+use crate::{decode, Instruction};
+
+const BINARY_MAGIC: &[u8; 8] = b"ICTRACE1";
+
+// One executed instruction: the operand values it read, the memory it wrote and the relative
+// base once it had run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub ip: i64,
+    pub instruction: Instruction,
+    pub operands: Vec<i64>,
+    pub writes: Vec<(i64, i64)>,
+    pub base: i64
+}
+
+impl Record {
+    pub fn new(ip: i64, instruction: Instruction) -> Record {
+        Record { ip, instruction, operands: vec!(), writes: vec!(), base: 0 }
+    }
+
+    pub fn to_json(&self, step: u64) -> String {
+        let join = |values: Vec<String>| values.join(",");
+        let mut json = String::new();
+        write!(json, "{{\"step\":{},\"ip\":{},\"word\":{},\"instruction\":\"{}\"",
+               step, self.ip, self.instruction.word(), self.instruction).unwrap();
+        write!(json, ",\"operands\":[{}]", join(self.operands.iter().map(|v| v.to_string()).collect())).unwrap();
+        write!(json, ",\"writes\":[{}]", join(self.writes.iter().map(|(a, v)| format!("[{},{}]", a, v)).collect())).unwrap();
+        write!(json, ",\"base\":{}}}", self.base).unwrap();
+        json
+    }
+}
+
+pub trait Tracer: Send {
+    fn record(&mut self, record: &Record);
+
+    // Flushes anything buffered and reports the first error hit while recording.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Writes one JSON object per line, which diffs nicely with ordinary text tools.
+pub struct JsonTracer<W: Write> {
+    out: BufWriter<W>,
+    step: u64,
+    error: Option<io::Error>
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(out: W) -> JsonTracer<W> {
+        JsonTracer { out: BufWriter::new(out), step: 0, error: None }
+    }
+}
+
+impl<W: Write + Send> Tracer for JsonTracer<W> {
+    fn record(&mut self, record: &Record) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.out, "{}", record.to_json(self.step)) {
+                self.error = Some(e);
+            }
+        }
+        self.step += 1;
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush()
+        }
+    }
+}
+
+// Writes records as little-endian words after a short header. Each record is the ip, the
+// instruction's words, the base, then counted lists of operands and (address, value) writes.
+pub struct BinaryTracer<W: Write> {
+    out: BufWriter<W>,
+    started: bool,
+    error: Option<io::Error>
+}
+
+impl<W: Write> BinaryTracer<W> {
+    pub fn new(out: W) -> BinaryTracer<W> {
+        BinaryTracer { out: BufWriter::new(out), started: false, error: None }
+    }
+
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        if !self.started {
+            self.out.write_all(BINARY_MAGIC)?;
+            self.started = true;
+        }
+
+        let mut words = vec!(record.ip, record.instruction.word());
+        words.extend(record.instruction.args().iter().map(|arg| arg.value));
+        words.push(record.base);
+        for word in words {
+            self.out.write_all(&word.to_le_bytes())?;
+        }
+
+        self.out.write_all(&[record.operands.len() as u8])?;
+        for operand in &record.operands {
+            self.out.write_all(&operand.to_le_bytes())?;
+        }
+        self.out.write_all(&[record.writes.len() as u8])?;
+        for (address, value) in &record.writes {
+            self.out.write_all(&address.to_le_bytes())?;
+            self.out.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> Tracer for BinaryTracer<W> {
+    fn record(&mut self, record: &Record) {
+        if self.error.is_none() {
+            if let Err(e) = self.write_record(record) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush()
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_word(bytes: &[u8], pos: &mut usize) -> io::Result<i64> {
+    let word = bytes.get(*pos..*pos + 8).ok_or_else(|| invalid("truncated trace"))?;
+    *pos += 8;
+    let mut buf = [0; 8];
+    buf.copy_from_slice(word);
+    Ok(i64::from_le_bytes(buf))
+}
+
+fn read_count(bytes: &[u8], pos: &mut usize) -> io::Result<usize> {
+    let count = *bytes.get(*pos).ok_or_else(|| invalid("truncated trace"))?;
+    *pos += 1;
+    Ok(count as usize)
+}
+
+pub fn read_binary<R: Read>(mut input: R) -> io::Result<Vec<Record>> {
+    let mut bytes = vec!();
+    input.read_to_end(&mut bytes)?;
+    if bytes.is_empty() {
+        return Ok(vec!());
+    }
+    if !bytes.starts_with(BINARY_MAGIC) {
+        return Err(invalid("not a binary intcode trace"));
+    }
+
+    let mut records = vec!();
+    let mut pos = BINARY_MAGIC.len();
+    while pos < bytes.len() {
+        let ip = read_word(&bytes, &mut pos)?;
+        let word = read_word(&bytes, &mut pos)?;
+        let argc = crate::argc(word % 100).ok_or_else(|| invalid("unknown op in trace"))?;
+        let mut words = vec!(word);
+        for _ in 0..argc {
+            words.push(read_word(&bytes, &mut pos)?);
+        }
+        let instruction = decode(|a| words[(a - ip) as usize], ip).map_err(|_| invalid("bad instruction in trace"))?;
+        let base = read_word(&bytes, &mut pos)?;
+
+        let mut operands = vec!();
+        for _ in 0..read_count(&bytes, &mut pos)? {
+            operands.push(read_word(&bytes, &mut pos)?);
+        }
+        let mut writes = vec!();
+        for _ in 0..read_count(&bytes, &mut pos)? {
+            writes.push((read_word(&bytes, &mut pos)?, read_word(&bytes, &mut pos)?));
+        }
+
+        records.push(Record { ip, instruction, operands, writes, base });
+    }
+
+    Ok(records)
+}
+
+// The step at which two traces stop agreeing, if they ever do.
+pub fn first_divergence(a: &[Record], b: &[Record]) -> Option<usize> {
+    match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(step) => Some(step),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vm;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuffer {
+        fn bytes(&self) -> Vec<u8> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn traced(program: Vec<i64>, inputs: Vec<i64>, tracer: Box<dyn Tracer>) -> Vm {
+        let mut vm = Vm::with_inputs(program, inputs);
+        vm.tracer = Some(tracer);
+        vm.run().unwrap();
+        vm.tracer.as_mut().unwrap().finish().unwrap();
+        vm
+    }
+
+    const PROGRAM: [i64; 12] = [109,5,203,6,1002,11,3,100,204,95,99,0];
+
+    #[test]
+    fn test_json_lines() {
+        let buffer = SharedBuffer::default();
+        traced(PROGRAM.to_vec(), vec!(7), Box::new(JsonTracer::new(buffer.clone())));
+
+        let text = String::from_utf8(buffer.bytes()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines, vec!(
+            r#"{"step":0,"ip":0,"word":109,"instruction":"ARB  #5","operands":[5],"writes":[],"base":5}"#,
+            r#"{"step":1,"ip":2,"word":203,"instruction":"IN   [rb+6]","operands":[],"writes":[[11,7]],"base":5}"#,
+            r#"{"step":2,"ip":4,"word":1002,"instruction":"MUL  [11], #3, [100]","operands":[7,3],"writes":[[100,21]],"base":5}"#,
+            r#"{"step":3,"ip":8,"word":204,"instruction":"OUT  [rb+95]","operands":[21],"writes":[],"base":5}"#,
+            r#"{"step":4,"ip":10,"word":99,"instruction":"HLT","operands":[],"writes":[],"base":5}"#
+        ));
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let json = SharedBuffer::default();
+        traced(PROGRAM.to_vec(), vec!(7), Box::new(JsonTracer::new(json.clone())));
+        let binary = SharedBuffer::default();
+        traced(PROGRAM.to_vec(), vec!(7), Box::new(BinaryTracer::new(binary.clone())));
+
+        let records = read_binary(&binary.bytes()[..]).unwrap();
+        let from_binary: Vec<String> = records.iter().enumerate()
+            .map(|(step, record)| record.to_json(step as u64))
+            .collect();
+        let text = String::from_utf8(json.bytes()).unwrap();
+        assert_eq!(from_binary, text.lines().collect::<Vec<&str>>());
+        assert_eq!(records[1].writes, vec!((11, 7)));
+    }
+
+    #[test]
+    fn test_divergence() {
+        let run = |input: i64| {
+            let buffer = SharedBuffer::default();
+            traced(PROGRAM.to_vec(), vec!(input), Box::new(BinaryTracer::new(buffer.clone())));
+            read_binary(&buffer.bytes()[..]).unwrap()
+        };
+
+        assert_eq!(first_divergence(&run(7), &run(7)), None);
+        assert_eq!(first_divergence(&run(7), &run(8)), Some(1));
+        assert_eq!(first_divergence(&run(7)[..2], &run(7)), Some(2));
+        assert!(read_binary(&b"nonsense"[..]).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::{async_stdin, clear, cursor};
+
+use intcode::arcade::{Arcade, Joystick, Screen};
+use intcode::snapshot::Snapshot;
+use intcode::Vm;
+
+const TICK: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+    Save,
+    Load,
+    Quit
+}
+
+// A joystick worked from the keyboard: the arrows or a and d tilt it, and it springs back to
+// neutral on any tick without a key. The game runs at one tick per read whether or not anything
+// is pressed.
+pub struct Keyboard<K, W> {
+    keys: K,
+    out: W,
+    tick: Duration,
+    last: Option<Instant>,
+    pub command: Option<Command>,
+    pub message: String
+}
+
+impl<K: Iterator<Item = io::Result<Key>>, W: Write> Keyboard<K, W> {
+    pub fn new(keys: K, out: W, tick: Duration) -> Keyboard<K, W> {
+        Keyboard { keys, out, tick, last: None, command: None, message: String::new() }
+    }
+
+    pub fn show(&mut self, screen: &Screen) {
+        // raw mode doesn't turn \n into \r\n
+        let text = screen.to_string().replace('\n', "\r\n");
+        write!(self.out, "{}{}{}\r\n← → or a d to move, s to save, l to load, q to quit\r\n{}",
+               clear::All, cursor::Goto(1, 1), text, self.message).expect("failed to draw");
+        self.out.flush().expect("failed to draw");
+    }
+}
+
+impl<K: Iterator<Item = io::Result<Key>>, W: Write> Joystick for Keyboard<K, W> {
+    fn tilt(&mut self, screen: &Screen) -> Option<i64> {
+        self.show(screen);
+        if let Some(last) = self.last {
+            thread::sleep(self.tick.saturating_sub(last.elapsed()));
+        }
+        self.last = Some(Instant::now());
+
+        // the last key pressed in the tick wins
+        let mut tilt = 0;
+        while let Some(Ok(key)) = self.keys.next() {
+            match key {
+                Key::Left | Key::Char('a') => tilt = -1,
+                Key::Right | Key::Char('d') => tilt = 1,
+                Key::Down | Key::Char(' ') => tilt = 0,
+                Key::Char('s') => self.command = Some(Command::Save),
+                Key::Char('l') => self.command = Some(Command::Load),
+                Key::Char('q') | Key::Ctrl('c') => self.command = Some(Command::Quit),
+                _ => {}
+            }
+            if self.command.is_some() {
+                return None;
+            }
+        }
+        Some(tilt)
+    }
+}
+
+// Runs the game until the player quits, saving and loading when asked. When the game ends it
+// waits for the player to load or quit.
+pub fn play<K, W>(vm: &mut Vm, arcade: &mut Arcade<Keyboard<K, W>>, save: &Path) -> io::Result<()>
+    where K: Iterator<Item = io::Result<Key>>, W: Write
+{
+    loop {
+        vm.run_with(arcade).map_err(|e| io::Error::other(e.to_string()))?;
+        match arcade.joystick.command.take() {
+            Some(Command::Save) => {
+                arcade.save(vm).save(save)?;
+                arcade.joystick.message = format!("saved to {}", save.display());
+            },
+            Some(Command::Load) => match Snapshot::load(save) {
+                Ok(snapshot) => {
+                    arcade.load(vm, &snapshot);
+                    arcade.joystick.message = format!("loaded {}", save.display());
+                },
+                Err(e) => arcade.joystick.message = format!("couldn't load {}: {}", save.display(), e)
+            },
+            Some(Command::Quit) => return Ok(()),
+            None => {
+                arcade.joystick.message = "game over, l to load or q to quit".to_string();
+                let screen = arcade.screen.clone();
+                while arcade.joystick.tilt(&screen).is_some() {}
+            }
+        }
+    }
+}
+
+// Plays the game in the terminal at a fixed tick rate.
+pub fn run(ints: &[i64], save: &Path) -> io::Result<()> {
+    let mut vm = Vm::new(ints.to_vec());
+    vm.write(0, 2).expect("vm error");
+
+    let mut out = io::stdout().into_raw_mode()?;
+    write!(out, "{}", cursor::Hide)?;
+    let mut arcade = Arcade::new(Keyboard::new(async_stdin().keys(), out, TICK));
+    let result = play(&mut vm, &mut arcade, save);
+    write!(arcade.joystick.out, "{}\r\n", cursor::Show)?;
+    let score = arcade.screen.score;
+    // raw mode ends when the terminal is dropped with the keyboard
+    drop(arcade);
+    println!("score: {}", score);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use intcode::arcade::{Autopilot, BALL, PADDLE};
+
+    // Keys as they'd arrive, with None between ticks.
+    struct Ticks(VecDeque<Option<Key>>);
+
+    impl Iterator for Ticks {
+        type Item = io::Result<Key>;
+
+        fn next(&mut self) -> Option<io::Result<Key>> {
+            self.0.pop_front().flatten().map(Ok)
+        }
+    }
+
+    fn keyboard(ticks: &[&[Key]]) -> Keyboard<Ticks, Vec<u8>> {
+        let mut keys = VecDeque::new();
+        for tick in ticks {
+            keys.extend(tick.iter().copied().map(Some));
+            keys.push_back(None);
+        }
+        Keyboard::new(Ticks(keys), vec!(), Duration::from_millis(0))
+    }
+
+    fn new_game() -> Vm {
+        let mut vm = Vm::new(crate::read_input());
+        vm.write(0, 2).unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut screen = Screen::default();
+        screen.draw(3, 4, PADDLE);
+        screen.score = 42;
+
+        let mut keys = keyboard(&[&[Key::Left, Key::Char('d')], &[], &[Key::Char('a')]]);
+        assert_eq!(keys.tilt(&screen), Some(1));
+        assert_eq!(keys.tilt(&screen), Some(0));
+        assert_eq!(keys.tilt(&screen), Some(-1));
+        assert!(String::from_utf8(keys.out.clone()).unwrap().contains("score 42\r\n"));
+
+        let mut keys = keyboard(&[&[Key::Right, Key::Char('s'), Key::Left]]);
+        assert_eq!(keys.tilt(&screen), None);
+        assert_eq!(keys.command.take(), Some(Command::Save));
+        assert_eq!(keys.tilt(&screen), Some(-1));
+    }
+
+    // The keys for the autopilot's first moves, which keep the ball in play.
+    fn autopilot(moves: usize) -> Vec<&'static [Key]> {
+        let mut arcade = Arcade::new(Autopilot);
+        new_game().run_with(&mut arcade).unwrap();
+        arcade.recording.frames.iter().take(moves).map(|frame| match frame.joystick {
+            Some(-1) => &[Key::Left] as &[Key],
+            Some(1) => &[Key::Right],
+            _ => &[]
+        }).collect()
+    }
+
+    #[test]
+    fn test_save_load_and_quit() {
+        let save = std::env::temp_dir().join(format!("problem13-save-{}", std::process::id()));
+        let moves = autopilot(40);
+
+        // play a while, save, carry on and then go back to the save before quitting
+        let mut ticks = moves[..20].to_vec();
+        ticks.push(&[Key::Char('s')]);
+        ticks.extend(&moves[20..]);
+        // a command cuts the tick short, so anything after it in the tick comes straight after
+        ticks.push(&[Key::Char('l'), Key::Char('q')]);
+        let mut vm = new_game();
+        let mut arcade = Arcade::new(keyboard(&ticks));
+        play(&mut vm, &mut arcade, &save).unwrap();
+        std::fs::remove_file(&save).unwrap();
+        assert_eq!(arcade.joystick.message, format!("loaded {}", save.display()));
+
+        // the same moves from the start end up in the same place
+        let mut ticks = moves[..20].to_vec();
+        ticks.push(&[Key::Char('q')]);
+        let mut again = new_game();
+        let mut replay = Arcade::new(keyboard(&ticks));
+        play(&mut again, &mut replay, &save).unwrap();
+
+        assert!(!vm.halted());
+        assert!(vm.snapshot() == again.snapshot());
+        assert_eq!(arcade.screen.to_string(), replay.screen.to_string());
+        assert!(arcade.screen.tiles.crop().iter().flatten().any(|tile| *tile == BALL));
+    }
+}
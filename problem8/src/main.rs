@@ -1,6 +1,8 @@
 use std::fs::File;
 use std::io::{BufReader, BufRead};
 
+use display::ocr;
+
 const WIDTH: usize = 25;
 const HEIGHT: usize = 6;
 
@@ -30,18 +32,17 @@ fn part1(layers: &Vec<Layer>) {
 }
 
 fn count_ns(layer: &Layer, n: u8) -> u64 {
-    let mut count: u64 = 0;
-    for y in 0..HEIGHT {
-        for x in 0..WIDTH {
-            if layer[y][x] == n {
-                count += 1;
-            }
-        }
-    }
-    count
+    layer.iter().flatten().filter(|pixel| **pixel == n).count() as u64
 }
 
 fn part2(layers: &Vec<Layer>) {
+    let picture = flatten(layers);
+    println!("{}", picture);
+    println!("part 2: {}", ocr::read_str(&picture).expect("failed to read the message"));
+}
+
+// The image with the layers stacked, lit pixels as '@'.
+fn flatten(layers: &[Layer]) -> String {
     let mut combined = [[b'-'; WIDTH]; HEIGHT];
 
     for layer in layers {
@@ -59,10 +60,10 @@ fn part2(layers: &Vec<Layer>) {
         }
     }
 
-    println!("part 2:");
-    for y in 0..HEIGHT {
-        println!("{}", std::str::from_utf8(&combined[y]).expect("failed to parse to string"));
-    }
+    combined.iter()
+        .map(|row| std::str::from_utf8(row).expect("failed to parse to string"))
+        .collect::<Vec<&str>>()
+        .join("\n")
 }
 
 fn parse_input() -> Vec<Layer> {
@@ -79,15 +80,24 @@ fn parse_input() -> Vec<Layer> {
     let mut c = input.iter();
 
     let mut result: Vec<Layer> = vec!();
-    for layer in 0..layers {
-        result.push([[0; WIDTH]; HEIGHT]);
+    for _ in 0..layers {
+        let mut layer = [[0; WIDTH]; HEIGHT];
 
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                result[layer][y][x] = c.next().expect("ran out of input") - b'0';
-            }
+        for pixel in layer.iter_mut().flatten() {
+            *pixel = c.next().expect("ran out of input") - b'0';
         }
+        result.push(layer);
     }
 
     result
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        assert_eq!(ocr::read_str(&flatten(&parse_input())), Ok("YGRYZ".to_string()));
+    }
+}